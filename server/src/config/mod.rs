@@ -10,19 +10,396 @@ pub use state::AppState;
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// seconds given to in-flight requests to finish after a shutdown signal
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct FileStorageConfig {
     pub storage_path: String,
+    /// nest stored files in subdirectories named after the leading hex digits of their id,
+    /// e.g. `2/2` stores `ab12....txt` at `ab/12/ab12....txt`. Flat when absent
+    pub shard: Option<String>,
+    #[serde(default)]
+    pub durability: Durability,
+    /// algorithm clients hash uploads with, stored hashes of another algorithm stay valid
+    #[serde(default)]
+    pub hash: crate::utils::HashAlgorithm,
+}
+
+/// When uploaded content is flushed to disk with `fsync`, each level includes the ones before
+/// it. The index is always synced.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// leave it to the OS, an indexed file can lose its tail in a power failure
+    #[default]
+    None,
+    /// before a completed upload is indexed
+    OnFinalize,
+    /// also after each part appended through `upload-part`
+    PerPart,
+    /// after every chunk written, slowest on spinning and network disks
+    PerChunk,
+}
+
+impl FileStorageConfig {
+    /// Width of each subdirectory level, empty for the flat layout
+    pub fn shard_levels(&self) -> anyhow::Result<Vec<usize>> {
+        let Some(shard) = &self.shard else {
+            return Ok(Vec::new());
+        };
+        let levels = shard
+            .split('/')
+            .map(|it| it.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|it| it.iter().all(|width| (1..=4).contains(width)))
+            .filter(|it| it.iter().sum::<usize>() <= 8);
+        levels.ok_or_else(|| {
+            anyhow!(
+                "Error: Invalid configuration, file_storage.shard '{}' must be widths of 1 to 4 separated by '/', 8 digits at most",
+                shard
+            )
+        })
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct LogConfig {
     #[serde(deserialize_with = "level_deserialize")]
     pub level: Level,
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// human-readable lines
+    #[default]
+    Text,
+    /// one JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AccessLogConfig {
+    /// file the lines are appended to, relative to the working directory
+    pub path: String,
+    /// `text` writes the Combined Log Format followed by the latency
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NotifyConfig {
+    /// number of recent events kept for `Last-Event-ID` replay
+    #[serde(default = "default_replay_buffer_size")]
+    pub replay_buffer_size: usize,
+    /// seconds between keep-alive comments on an idle stream
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u64,
+    /// seconds without an event after which a stream is closed, the client reconnects and
+    /// resumes from `Last-Event-ID`. Streams stay open indefinitely when absent
+    pub max_idle: Option<u64>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            replay_buffer_size: default_replay_buffer_size(),
+            keep_alive: default_keep_alive(),
+            max_idle: None,
+        }
+    }
+}
+
+fn default_replay_buffer_size() -> usize {
+    64
+}
+
+fn default_keep_alive() -> u64 {
+    15
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StreamingConfig {
+    /// size in bytes of each chunk read from disk when streaming a file
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    /// most ranges accepted in one `Range` header, more are answered with a `416`
+    #[serde(default = "default_max_ranges")]
+    pub max_ranges: usize,
+    /// report how the `Range` header was interpreted in an `X-Debug-Range` response header
+    #[serde(default)]
+    pub debug_range: bool,
+    /// bytes per second each download is limited to, unlimited when absent
+    pub rate_limit: Option<u64>,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: default_chunk_size(),
+            max_ranges: default_max_ranges(),
+            debug_range: false,
+            rate_limit: None,
+        }
+    }
+}
+
+fn default_chunk_size() -> usize {
+    4096
+}
+
+fn default_max_ranges() -> usize {
+    8
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CompressionConfig {
+    /// gzip text responses for clients that accept it, trades CPU for bandwidth
+    #[serde(default)]
+    pub enabled: bool,
+    /// files smaller than this many bytes are sent as is
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_size: default_compression_min_size(),
+        }
+    }
+}
+
+fn default_compression_min_size() -> u64 {
+    1024
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ArchiveConfig {
+    /// largest total size in bytes of the files downloaded as one archive
+    #[serde(default = "default_archive_max_size")]
+    pub max_size: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_archive_max_size(),
+        }
+    }
+}
+
+fn default_archive_max_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MetadataConfig {
+    /// most ids one batch metadata request may ask for
+    #[serde(default = "default_metadata_max_batch_size")]
+    pub max_batch_size: usize,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: default_metadata_max_batch_size(),
+        }
+    }
+}
+
+fn default_metadata_max_batch_size() -> usize {
+    100
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct SweepConfig {
+    /// seconds between two sweeps of orphaned upload parts, the first runs at startup
+    #[serde(default = "default_sweep_interval")]
+    pub interval: u64,
+    /// seconds a part file without an upload session is kept before it is deleted, and a
+    /// session without activity before it is abandoned
+    #[serde(default = "default_sweep_max_age")]
+    pub max_age: u64,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            interval: default_sweep_interval(),
+            max_age: default_sweep_max_age(),
+        }
+    }
+}
+
+fn default_sweep_interval() -> u64 {
+    3600
+}
+
+fn default_sweep_max_age() -> u64 {
+    86400
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct UploadConfig {
+    /// resumable `upload-part` sessions one client address can have open at a time,
+    /// unlimited when absent. Behind a reverse proxy every client shares the proxy's address
+    pub max_sessions_per_client: Option<usize>,
+    /// bytes kept free on the storage volume, uploads that would cut into them are refused
+    /// up front. Not checked when absent, nor where the free space can't be read
+    pub reserved_space: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MirrorConfig {
+    /// directory of symlinks named after the original filenames, relative to the working
+    /// directory like `file_storage.storage_path`
+    pub path: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct BlocklistConfig {
+    /// filename extensions refused for upload, without the dot, e.g. `exe`
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// mime types refused for upload, `type/*` or a trailing `*` matches a prefix, e.g.
+    /// `application/x-sh*`
+    #[serde(default)]
+    pub mimetypes: Vec<String>,
+}
+
+impl BlocklistConfig {
+    pub fn blocks_filename(&self, filename: &str) -> bool {
+        let Some(ext) = std::path::Path::new(filename).extension() else {
+            return false;
+        };
+        let ext = ext.to_string_lossy();
+        self.extensions
+            .iter()
+            .any(|it| it.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+    }
+    /// Parameters such as `; charset=utf-8` are ignored
+    pub fn blocks_mimetype(&self, mimetype: &str) -> bool {
+        let essence = mimetype
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase();
+        self.mimetypes.iter().any(|it| {
+            let pattern = it.trim().to_lowercase();
+            match pattern.strip_suffix('*') {
+                Some(prefix) => essence.starts_with(prefix),
+                None => essence == pattern,
+            }
+        })
+    }
+    /// Check of what the client declared, the content can still be sniffed as a blocked type
+    pub fn blocks(&self, filename: Option<&str>, mimetype: Option<&str>) -> bool {
+        filename.is_some_and(|it| self.blocks_filename(it))
+            || mimetype.is_some_and(|it| self.blocks_mimetype(it))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    /// sign the body with HMAC-SHA256 into the `X-Signature` header if present
+    pub secret: Option<String>,
+    /// request timeout in seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+    /// number of retries after a failed delivery
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+}
+
+fn default_webhook_timeout() -> u64 {
+    5
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AdminConfig {
+    /// token expected in the `Access-Token` header of admin requests
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CorsConfig {
+    /// allowed origins, `*` allows any origin
+    #[serde(default = "default_wildcard")]
+    pub allow_origins: Vec<String>,
+    /// allowed methods, `*` allows any method
+    #[serde(default = "default_wildcard")]
+    pub allow_methods: Vec<String>,
+    #[serde(default = "default_cors_allow_headers")]
+    pub allow_headers: Vec<String>,
+    /// response headers readable by scripts, on top of the CORS-safelisted ones
+    #[serde(default = "default_cors_expose_headers")]
+    pub expose_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// seconds a preflight response may be cached
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allow_origins: default_wildcard(),
+            allow_methods: default_wildcard(),
+            allow_headers: default_cors_allow_headers(),
+            expose_headers: default_cors_expose_headers(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+fn default_wildcard() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_cors_allow_headers() -> Vec<String> {
+    [
+        "content-type",
+        "content-range",
+        "access-token",
+        "x-content-sha256",
+        "x-content-length",
+        "x-raw-filename",
+        "if-match",
+        "last-event-id",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+fn default_cors_expose_headers() -> Vec<String> {
+    ["location", "etag", "content-range"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// shape of a future `[https]` section, nothing reads it yet
+#[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone)]
 pub struct HttpsConfig {
     pub port: u16,
@@ -53,12 +430,112 @@ pub(crate) struct Config {
     pub server: ServerConfig,
     pub file_storage: FileStorageConfig,
     pub log: LogConfig,
+    /// no access log is written when absent
+    pub access_log: Option<AccessLogConfig>,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    pub webhooks: Option<WebhookConfig>,
+    #[serde(default)]
+    pub streaming: StreamingConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub upload: UploadConfig,
+    #[serde(default)]
+    pub sweep: SweepConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    /// every upload is accepted when absent
+    #[serde(default)]
+    pub blocklist: BlocklistConfig,
+    /// permissive policy when absent
+    pub cors: Option<CorsConfig>,
+    /// admin endpoints are disabled when absent
+    pub admin: Option<AdminConfig>,
+    /// no mirror is maintained when absent
+    pub mirror: Option<MirrorConfig>,
 }
 
 impl Config {
     pub(crate) fn read_storage_dir(&self) -> std::path::PathBuf {
         utils::read_path(&self.file_storage.storage_path)
     }
+    pub(crate) fn read_access_log_path(&self) -> Option<std::path::PathBuf> {
+        self.access_log
+            .as_ref()
+            .map(|it| utils::read_path(&it.path))
+    }
+    pub(crate) fn read_mirror_dir(&self) -> Option<std::path::PathBuf> {
+        self.mirror.as_ref().map(|it| utils::read_path(&it.path))
+    }
+    /// Check the values serde can't express
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.streaming.chunk_size == 0 {
+            return Err(anyhow!(
+                "Error: Invalid configuration, streaming.chunk_size must be greater than 0"
+            ));
+        }
+        if self.notify.keep_alive == 0 || self.notify.max_idle == Some(0) {
+            return Err(anyhow!(
+                "Error: Invalid configuration, notify.keep_alive and notify.max_idle must be greater than 0"
+            ));
+        }
+        if self.streaming.rate_limit == Some(0) {
+            return Err(anyhow!(
+                "Error: Invalid configuration, streaming.rate_limit must be greater than 0"
+            ));
+        }
+        if self.streaming.max_ranges == 0 {
+            return Err(anyhow!(
+                "Error: Invalid configuration, streaming.max_ranges must be greater than 0"
+            ));
+        }
+        if self.admin.as_ref().is_some_and(|it| it.token.is_empty()) {
+            return Err(anyhow!(
+                "Error: Invalid configuration, admin.token must not be empty"
+            ));
+        }
+        self.file_storage.shard_levels()?;
+        if self.read_mirror_dir() == Some(self.read_storage_dir()) {
+            return Err(anyhow!(
+                "Error: Invalid configuration, mirror.path must differ from file_storage.storage_path"
+            ));
+        }
+        if self.upload.max_sessions_per_client == Some(0) {
+            return Err(anyhow!(
+                "Error: Invalid configuration, upload.max_sessions_per_client must be greater than 0"
+            ));
+        }
+        if self.metadata.max_batch_size == 0 {
+            return Err(anyhow!(
+                "Error: Invalid configuration, metadata.max_batch_size must be greater than 0"
+            ));
+        }
+        if self.sweep.interval == 0 {
+            return Err(anyhow!(
+                "Error: Invalid configuration, sweep.interval must be greater than 0"
+            ));
+        }
+        if let Some(cors) = &self.cors {
+            let has_wildcard = [
+                &cors.allow_origins,
+                &cors.allow_methods,
+                &cors.allow_headers,
+                &cors.expose_headers,
+            ]
+            .iter()
+            .any(|it| it.iter().any(|it| it == "*"));
+            if cors.allow_credentials && has_wildcard {
+                return Err(anyhow!(
+                    "Error: Invalid configuration, cors.allow_credentials can't be combined with a wildcard '*'.\n\
+                    Please list the allowed origins, methods and headers explicitly"
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 pub mod utils {
@@ -102,8 +579,38 @@ pub(crate) fn load() -> anyhow::Result<Config> {
         "Error: Failed to read configuration file.\n\
         Please check the file path and file permissions, and make sure the file is valid accessible"
     })?;
-    toml::from_str(&content).with_context(|| {
+    let config: Config = toml::from_str(&content).with_context(|| {
         "Error: Failed to parse configuration file.\n\
         Please check the file syntax is valid TOML syntax"
-    })
+    })?;
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist() {
+        let blocklist = BlocklistConfig {
+            extensions: vec!["exe".to_string(), ".bat".to_string()],
+            mimetypes: vec![
+                "application/x-sh*".to_string(),
+                "application/vnd.microsoft.portable-executable".to_string(),
+            ],
+        };
+        assert!(blocklist.blocks_filename("setup.EXE"));
+        assert!(blocklist.blocks_filename("run.bat"));
+        assert!(!blocklist.blocks_filename("notes.txt"));
+        assert!(!blocklist.blocks_filename("exe"));
+        assert!(blocklist.blocks_mimetype("application/x-sh; charset=utf-8"));
+        assert!(blocklist.blocks_mimetype("application/x-shellscript"));
+        assert!(!blocklist.blocks_mimetype("text/plain"));
+        // a renamed executable is still caught by its sniffed type
+        assert!(!blocklist.blocks(Some("image.png"), Some("image/png")));
+        let sniffed = crate::utils::guess_mimetype(b"MZ\x90\0\x03\0\0\0", Some("image.png"));
+        assert!(blocklist.blocks_mimetype(&sniffed));
+        assert!(!BlocklistConfig::default().blocks(Some("setup.exe"), Some("application/x-sh")));
+    }
 }
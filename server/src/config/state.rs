@@ -1,11 +1,36 @@
-use crate::{config, models};
+use crate::{config, models, utils};
 use std::sync::Arc;
-use tokio::sync::broadcast;
 
 #[allow(unused)]
 #[derive(Clone)]
 pub struct AppState {
     pub(crate) config: Arc<config::Config>,
     pub(crate) bucket: Arc<models::Bucket>,
-    pub(crate) broadcast: broadcast::Sender<models::bucket::BucketAction>,
+    pub(crate) broadcast: Arc<models::Notifier>,
+    pub(crate) shutdown: utils::Shutdown,
+    pub(crate) upload_sessions: Arc<models::UploadSessions>,
+    pub(crate) connections: Arc<models::Connections>,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// Default settings over an empty bucket in `dir`, for driving the handlers in tests
+    pub(crate) async fn for_test(dir: &std::path::Path) -> Self {
+        let config: config::Config = toml::from_str(&format!(
+            "[server]\nhost = \"127.0.0.1\"\nport = 0\n\
+            [file_storage]\nstorage_path = {:?}\n\
+            [log]\nlevel = \"info\"\n",
+            dir.to_string_lossy()
+        ))
+        .unwrap();
+        let bucket = models::Bucket::connect(config.read_storage_dir(), Vec::new()).await;
+        Self {
+            broadcast: Arc::new(models::Notifier::new(config.notify.replay_buffer_size)),
+            config: Arc::new(config),
+            bucket: Arc::new(bucket),
+            shutdown: utils::Shutdown::default(),
+            upload_sessions: Arc::new(models::UploadSessions::default()),
+            connections: Arc::new(models::Connections::default()),
+        }
+    }
 }
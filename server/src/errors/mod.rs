@@ -1,6 +1,7 @@
 use std::fmt::{Display, Formatter, Result};
 
 #[allow(unused)]
+#[derive(Debug)]
 pub enum ApiError<'a> {
     QueryFieldMissing(&'a str),
     HeaderFieldMissing(&'a str),
@@ -12,6 +13,65 @@ pub enum ApiError<'a> {
     RangeNotFound,
     ResourceNotFound,
     HashMismatch,
+    ServerShuttingDown,
+    AdminDisabled,
+    InvalidAccessToken,
+    ArchiveTooLarge { size: u64, limit: u64 },
+    ResumeUnavailable { offset: Option<u64> },
+    ETagMismatch,
+    ForbiddenContentType,
+    UnexpectedHashAlgorithm { expected: &'a str },
+    TooManySessions { limit: usize },
+    DiskQuotaExceeded,
+    BatchTooLarge { limit: usize },
+}
+
+impl ApiError<'_> {
+    /// Stable machine-readable code, clients branch on this rather than the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::QueryFieldMissing(_) => "QUERY_FIELD_MISSING",
+            ApiError::HeaderFieldMissing(_) => "HEADER_FIELD_MISSING",
+            ApiError::BodyFieldMissing(_) => "BODY_FIELD_MISSING",
+            ApiError::PathParameterMissing => "PATH_PARAMETER_MISSING",
+            ApiError::RangeTooLarge => "RANGE_TOO_LARGE",
+            ApiError::RangeNotSupported => "RANGE_NOT_SUPPORTED",
+            ApiError::InvalidRange => "INVALID_RANGE",
+            ApiError::RangeNotFound => "RANGE_NOT_FOUND",
+            ApiError::ResourceNotFound => "RESOURCE_NOT_FOUND",
+            ApiError::HashMismatch => "HASH_MISMATCH",
+            ApiError::ServerShuttingDown => "SERVER_SHUTTING_DOWN",
+            ApiError::AdminDisabled => "ADMIN_DISABLED",
+            ApiError::InvalidAccessToken => "INVALID_ACCESS_TOKEN",
+            ApiError::ArchiveTooLarge { .. } => "ARCHIVE_TOO_LARGE",
+            ApiError::ResumeUnavailable { .. } => "RESUME_UNAVAILABLE",
+            ApiError::ETagMismatch => "ETAG_MISMATCH",
+            ApiError::ForbiddenContentType => "FORBIDDEN_CONTENT_TYPE",
+            ApiError::UnexpectedHashAlgorithm { .. } => "UNEXPECTED_HASH_ALGORITHM",
+            ApiError::TooManySessions { .. } => "TOO_MANY_SESSIONS",
+            ApiError::DiskQuotaExceeded => "DISK_QUOTA_EXCEEDED",
+            ApiError::BatchTooLarge { .. } => "BATCH_TOO_LARGE",
+        }
+    }
+    /// Structured data about the error, if any
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            ApiError::QueryFieldMissing(field)
+            | ApiError::HeaderFieldMissing(field)
+            | ApiError::BodyFieldMissing(field) => Some(serde_json::json!({ "field": field })),
+            ApiError::ArchiveTooLarge { size, limit } => {
+                Some(serde_json::json!({ "size": size, "limit": limit }))
+            }
+            ApiError::ResumeUnavailable { offset } => Some(serde_json::json!({ "offset": offset })),
+            ApiError::UnexpectedHashAlgorithm { expected } => {
+                Some(serde_json::json!({ "expected": expected }))
+            }
+            ApiError::TooManySessions { limit } | ApiError::BatchTooLarge { limit } => {
+                Some(serde_json::json!({ "limit": limit }))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Display for ApiError<'_> {
@@ -50,6 +110,61 @@ impl Display for ApiError<'_> {
                     "The SHA-256 hash does mismatch the expected value. [ERR-010]"
                 )
             }
+            ApiError::ServerShuttingDown => {
+                write!(f, "Server is shutting down, please retry later [ERR-011]")
+            }
+            ApiError::AdminDisabled => {
+                write!(f, "Admin endpoints are disabled [ERR-012]")
+            }
+            ApiError::InvalidAccessToken => {
+                write!(f, "Access token is missing or invalid [ERR-013]")
+            }
+            ApiError::ArchiveTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "Selection of {} bytes exceeds the archive limit of {} bytes [ERR-014]",
+                    size, limit
+                )
+            }
+            ApiError::ResumeUnavailable { .. } => {
+                write!(
+                    f,
+                    "No interrupted upload of this content can continue at this offset [ERR-015]"
+                )
+            }
+            ApiError::ETagMismatch => {
+                write!(
+                    f,
+                    "The resource does not match the If-Match header [ERR-016]"
+                )
+            }
+            ApiError::ForbiddenContentType => {
+                write!(f, "Files of this type can't be uploaded [ERR-017]")
+            }
+            ApiError::UnexpectedHashAlgorithm { expected } => {
+                write!(
+                    f,
+                    "X-Content-Sha256 must be a {} hash of the content [ERR-018]",
+                    expected
+                )
+            }
+            ApiError::TooManySessions { limit } => {
+                write!(
+                    f,
+                    "Too many uploads in progress, at most {} at a time [ERR-019]",
+                    limit
+                )
+            }
+            ApiError::DiskQuotaExceeded => {
+                write!(f, "Not enough free space to store the upload [ERR-020]")
+            }
+            ApiError::BatchTooLarge { limit } => {
+                write!(
+                    f,
+                    "At most {} ids can be requested at once [ERR-021]",
+                    limit
+                )
+            }
         }
     }
 }
@@ -127,3 +242,52 @@ impl<'a> Display for InternalError<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_error_code() {
+        let cases = [
+            (ApiError::QueryFieldMissing("q"), "QUERY_FIELD_MISSING"),
+            (ApiError::HeaderFieldMissing("h"), "HEADER_FIELD_MISSING"),
+            (ApiError::BodyFieldMissing("b"), "BODY_FIELD_MISSING"),
+            (ApiError::PathParameterMissing, "PATH_PARAMETER_MISSING"),
+            (ApiError::RangeTooLarge, "RANGE_TOO_LARGE"),
+            (ApiError::RangeNotSupported, "RANGE_NOT_SUPPORTED"),
+            (ApiError::InvalidRange, "INVALID_RANGE"),
+            (ApiError::RangeNotFound, "RANGE_NOT_FOUND"),
+            (ApiError::ResourceNotFound, "RESOURCE_NOT_FOUND"),
+            (ApiError::HashMismatch, "HASH_MISMATCH"),
+            (ApiError::ServerShuttingDown, "SERVER_SHUTTING_DOWN"),
+            (ApiError::AdminDisabled, "ADMIN_DISABLED"),
+            (ApiError::InvalidAccessToken, "INVALID_ACCESS_TOKEN"),
+            (
+                ApiError::ArchiveTooLarge { size: 2, limit: 1 },
+                "ARCHIVE_TOO_LARGE",
+            ),
+            (
+                ApiError::ResumeUnavailable { offset: None },
+                "RESUME_UNAVAILABLE",
+            ),
+            (ApiError::ETagMismatch, "ETAG_MISMATCH"),
+            (ApiError::ForbiddenContentType, "FORBIDDEN_CONTENT_TYPE"),
+            (
+                ApiError::UnexpectedHashAlgorithm { expected: "blake3" },
+                "UNEXPECTED_HASH_ALGORITHM",
+            ),
+            (ApiError::TooManySessions { limit: 1 }, "TOO_MANY_SESSIONS"),
+            (ApiError::DiskQuotaExceeded, "DISK_QUOTA_EXCEEDED"),
+            (ApiError::BatchTooLarge { limit: 1 }, "BATCH_TOO_LARGE"),
+        ];
+        for (err, code) in cases {
+            assert_eq!(err.code(), code);
+        }
+        assert_eq!(
+            ApiError::HeaderFieldMissing("Content-Type").details(),
+            Some(serde_json::json!({ "field": "Content-Type" }))
+        );
+        assert_eq!(ApiError::HashMismatch.details(), None);
+    }
+}
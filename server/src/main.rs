@@ -1,6 +1,7 @@
 use config::state;
 use std::net::ToSocketAddrs;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 mod config;
@@ -13,55 +14,168 @@ mod utils;
 #[tokio::main]
 async fn main() {
     let config = config::load().unwrap();
-    let config::ServerConfig { port, host } = config.server.clone();
-    let config::LogConfig { level } = config.log.clone();
-    let (tx, _) = tokio::sync::broadcast::channel(8);
+    let config::ServerConfig {
+        port,
+        host,
+        shutdown_timeout,
+    } = config.server.clone();
+    let config::LogConfig { level, format } = config.log.clone();
+    let json = format == config::LogFormat::Json;
     // Initialize logger tracing
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::fmt::layer()
-                .with_filter(tracing_subscriber::filter::LevelFilter::from_level(level))
-                .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
-                    metadata.target().starts_with("synclink")
-                })),
+            if json {
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .boxed()
+            } else {
+                tracing_subscriber::fmt::layer().boxed()
+            }
+            .with_filter(tracing_subscriber::filter::LevelFilter::from_level(level))
+            .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+                metadata.target().starts_with("synclink")
+            })),
         )
         .with(
-            tracing_subscriber::fmt::layer()
-                .compact()
-                .with_file(false)
-                .with_target(false)
-                .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
-                    metadata.target().starts_with("tower_http")
-                })),
+            if json {
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_file(false)
+                    .with_target(false)
+                    .boxed()
+            } else {
+                tracing_subscriber::fmt::layer()
+                    .compact()
+                    .with_file(false)
+                    .with_target(false)
+                    .boxed()
+            }
+            .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+                metadata.target().starts_with("tower_http")
+            })),
         )
         .with(
-            tracing_subscriber::fmt::layer()
-                .compact()
-                .with_filter(tracing_subscriber::filter::LevelFilter::INFO)
-                .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
-                    !metadata.target().starts_with("synclink")
-                })),
+            if json {
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .boxed()
+            } else {
+                tracing_subscriber::fmt::layer().compact().boxed()
+            }
+            .with_filter(tracing_subscriber::filter::LevelFilter::INFO)
+            .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+                !metadata.target().starts_with("synclink")
+            })),
         )
         .with(tracing_error::ErrorLayer::default())
         .init();
-    let bucket = Arc::new(models::Bucket::connect(config.read_storage_dir()).await);
+    let bucket = Arc::new(
+        models::Bucket::connect(
+            config.read_storage_dir(),
+            config.file_storage.shard_levels().unwrap(),
+        )
+        .await,
+    );
+    let notifier = Arc::new(models::Notifier::new(config.notify.replay_buffer_size));
+    if let Some(webhooks) = config.webhooks.clone() {
+        let (_, subscription) = notifier.subscribe(None);
+        models::webhook::Webhook::new(webhooks)
+            .unwrap()
+            .spawn(notifier.clone(), subscription);
+    }
+    if let Some(dir) = config.read_mirror_dir() {
+        let (_, subscription) = notifier.subscribe(None);
+        models::mirror::Mirror::new(dir, bucket.clone()).spawn(subscription.receiver);
+    }
+    let mut cors = routes::cors_layer(&config.cors.clone().unwrap_or_default()).unwrap();
+    if config.compression.enabled {
+        // the CORS layer overwrites `Vary`, so the encoding has to be listed there
+        use axum::http::header;
+        cors = cors.vary([
+            header::ORIGIN,
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            header::ACCESS_CONTROL_REQUEST_HEADERS,
+            header::ACCEPT_ENCODING,
+        ]);
+    }
+    let access_log = config.read_access_log_path().map(|path| {
+        let format = config.access_log.as_ref().unwrap().format;
+        Arc::new(
+            utils::AccessLog::open(&path, format)
+                .unwrap_or_else(|err| panic!("Error: Open access log {:?} failed: {}", path, err)),
+        )
+    });
+    let sweep = config.sweep.clone();
     let config = Arc::new(config);
+    let shutdown = utils::Shutdown::default();
+    let upload_sessions = Arc::new(models::UploadSessions::default());
+    spawn_sweeper(upload_sessions.clone(), sweep, shutdown.clone());
     let state = state::AppState {
         bucket,
         config,
-        broadcast: tx,
+        broadcast: notifier,
+        shutdown: shutdown.clone(),
+        upload_sessions,
+        connections: Arc::new(models::Connections::default()),
     };
-    let app = routes::routes();
+    let mut app = routes::routes(cors).layer(axum::middleware::from_fn_with_state(
+        shutdown.clone(),
+        utils::track_in_flight,
+    ));
+    if let Some(access_log) = access_log {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            access_log,
+            utils::access_log,
+        ));
+    }
     let addr = format!("{}:{}", host, port)
         .to_socket_addrs()
         .map(|mut it| it.next().unwrap())
         .unwrap();
     let server = axum::Server::bind(&addr)
-        .serve(app.with_state(state).into_make_service())
-        .with_graceful_shutdown(shutdown_signal());
+        .serve(
+            app.with_state(state)
+                .into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown.signal.clone().cancelled_owned());
+    tokio::spawn({
+        let signal = shutdown.signal.clone();
+        async move {
+            shutdown_signal().await;
+            signal.cancel();
+        }
+    });
 
     tracing::info!("Listening on http://{}", addr);
-    server.await.unwrap();
+    tokio::pin!(server);
+    tokio::select! {
+        biased;
+        _ = shutdown.signal.cancelled() => {},
+        result = &mut server => return result.unwrap(),
+    }
+    // No new connections are accepted from here, give in-flight requests a grace period
+    let in_flight = shutdown.in_flight();
+    tracing::info!("Shutdown, draining {} in-flight requests...", in_flight);
+    match tokio::time::timeout(Duration::from_secs(shutdown_timeout), &mut server).await {
+        Ok(result) => {
+            result.unwrap();
+            tracing::info!("Shutdown complete, {} requests drained", in_flight);
+        }
+        Err(_) => {
+            let remaining = shutdown.in_flight();
+            tracing::warn!(
+                "Grace period expired, {} requests drained, {} forcibly closed",
+                in_flight.saturating_sub(remaining),
+                remaining
+            );
+            // let aborted uploads remove their partial files before the runtime goes away
+            shutdown.abort.cancel();
+            let _ = tokio::time::timeout(Duration::from_secs(1), &mut server).await;
+        }
+    }
 }
 
 async fn shutdown_signal() {
@@ -81,10 +195,43 @@ async fn shutdown_signal() {
     #[cfg(not(unix))]
     let terminate = std::future::pending::<()>();
     tokio::select! {
-        _ = ctrl_c => {
-            println!("Shutdown...");
-            std::process::exit(0);
-        },
+        _ = ctrl_c => {},
         _ = terminate => {},
     }
 }
+
+/// Periodically remove part files no upload session refers to anymore
+fn spawn_sweeper(
+    sessions: Arc<models::UploadSessions>,
+    config: config::SweepConfig,
+    shutdown: utils::Shutdown,
+) {
+    let dir = models::upload_session::parts_dir();
+    let max_age = Duration::from_secs(config.max_age);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+        loop {
+            tokio::select! {
+                _ = shutdown.signal.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if !dir.exists() {
+                continue;
+            }
+            let sessions = sessions.clone();
+            let dir = dir.clone();
+            match tokio::task::spawn_blocking(move || sessions.sweep(&dir, max_age)).await {
+                Ok(Ok((0, _))) => {}
+                Ok(Ok((files, bytes))) => {
+                    tracing::info!(
+                        "Swept {} orphaned upload parts, {} bytes reclaimed",
+                        files,
+                        bytes
+                    )
+                }
+                Ok(Err(err)) => tracing::warn!(%err, "Failed to sweep upload parts"),
+                Err(err) => tracing::warn!(%err, "Upload parts sweeper panicked"),
+            }
+        }
+    });
+}
@@ -30,6 +30,10 @@ pub struct BucketEntity {
     name: String,
     /// hash of the content
     hash: String,
+    /// quick fingerprint of the content, see [`utils::probe_hash`]. Absent on entries stored
+    /// before probes were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    probe: Option<String>,
     /// length of content
     size: u64,
     /// mime type of the content
@@ -60,6 +64,9 @@ impl BucketEntity {
     pub fn get_hash(&self) -> &str {
         &self.hash
     }
+    pub fn get_probe(&self) -> &Option<String> {
+        &self.probe
+    }
     pub fn get_name(&self) -> &str {
         &self.name
     }
@@ -127,14 +134,146 @@ struct Index {
     items: Vec<BucketEntity>,
 }
 
+/// How recently a file must have been written to be taken for an upload in progress
+const ORPHAN_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Differences between the index and the storage directory found by [`Bucket::reconcile`]
+#[derive(Serialize, Debug, Default)]
+pub struct ReconcileReport {
+    /// files in the storage directory no entry refers to
+    pub orphaned_files: Vec<String>,
+    /// entries whose file is missing
+    pub dangling_entries: Vec<Uuid>,
+    /// entries whose recorded size differs from the file
+    pub inconsistent_entries: Vec<Uuid>,
+    /// entries of the index file skipped at startup because they failed to decode, by uid
+    /// when it is readable and by position otherwise. Never fixed, see [`Bucket::find_corrupt`]
+    pub corrupt_entries: Vec<String>,
+}
+
 pub(crate) struct Bucket {
     index: Arc<Mutex<Index>>,
-    index_file: std::fs::File,
+    /// reopened whenever the index is rewritten, since that replaces the file
+    index_file: Mutex<std::fs::File>,
     path: PathBuf,
+    /// width of each subdirectory level, see `FileStorageConfig::shard`
+    shard: Vec<usize>,
+    /// index entries that failed to decode at startup
+    corrupt: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RawIndex {
+    #[serde(rename = "item", default)]
+    items: Vec<toml::Value>,
+}
+
+/// Decode the index entry by entry, so one malformed entry doesn't take the others down.
+///
+/// Returns the index of the valid entries along with a label of each skipped one, its uid
+/// when readable or `#<position>` otherwise.
+fn parse_index(content: &str) -> Result<(Index, Vec<String>), toml::de::Error> {
+    let raw: RawIndex = toml::from_str(content)?;
+    let mut items = Vec::with_capacity(raw.items.len());
+    let mut corrupt = Vec::new();
+    for (pos, value) in raw.items.into_iter().enumerate() {
+        let label = value
+            .get("uid")
+            .and_then(|it| it.as_str())
+            .map_or_else(|| format!("#{}", pos), |it| it.to_string());
+        match value.try_into::<BucketEntity>() {
+            Ok(item) => items.push(item),
+            Err(err) => {
+                tracing::warn!(%err, "Skipping index entry {}, it failed to decode", label);
+                corrupt.push(label);
+            }
+        }
+    }
+    Ok((Index { items }, corrupt))
+}
+
+/// Subdirectories of a sharded file, taken from the leading hex digits of its id
+fn shard_dirs(uid: &Uuid, shard: &[usize]) -> PathBuf {
+    let hex = uid.simple().to_string();
+    let mut offset = 0;
+    let mut dirs = PathBuf::new();
+    for width in shard {
+        dirs.push(&hex[offset..offset + width]);
+        offset += width;
+    }
+    dirs
+}
+
+/// Replace the content of `path` so that a crash leaves either the old or the new content.
+///
+/// The content goes to `<path>.tmp` first, which is then renamed over `path`.
+fn replace_file(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut file = std::fs::File::create(&temp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    // persist the rename itself
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        std::fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Collect every file under `dir`, subdirectories included. Entries removed while walking are
+/// skipped.
+fn walk_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let is_gone = |err: &std::io::Error| err.kind() == std::io::ErrorKind::NotFound;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(err) if is_gone(&err) => continue,
+            Err(err) => return Err(err),
+        };
+        if file_type.is_dir() {
+            match walk_files(&entry.path(), files) {
+                Err(err) if !is_gone(&err) => return Err(err),
+                _ => {}
+            }
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Whether an entry is still as it was when reconcile took its snapshot
+fn is_unchanged(current: &BucketEntity, snapshot: &BucketEntity) -> bool {
+    current.uid == snapshot.uid
+        && current.hash == snapshot.hash
+        && current.size == snapshot.size
+        && current.ext == snapshot.ext
+        && current.modified == snapshot.modified
+}
+
+/// Entry with its size, hash and probe recomputed from the file at `path`
+fn rehash(item: &BucketEntity, path: &Path) -> std::io::Result<BucketEntity> {
+    let mut file = std::fs::File::open(path)?;
+    // keep the algorithm the entry was hashed with
+    let mut hasher = utils::HashAlgorithm::of(&item.hash)
+        .unwrap_or_default()
+        .hasher();
+    let size = std::io::copy(&mut file, &mut hasher)?;
+    Ok(BucketEntity {
+        hash: hasher.finalize(),
+        probe: Some(utils::probe_hash(path)?),
+        size,
+        modified: Some(chrono::Local::now().timestamp_millis()),
+        ..item.clone()
+    })
 }
 
 impl Bucket {
-    pub(crate) async fn connect(path: impl AsRef<Path>) -> Self {
+    pub(crate) async fn connect(path: impl AsRef<Path>, shard: Vec<usize>) -> Self {
         let path = path.as_ref().to_owned();
         if !&path.is_dir() {
             panic!("Error: Path '{:?}' is not a directory", path.as_os_str())
@@ -155,16 +294,89 @@ impl Bucket {
             .read_to_string(&mut index_content)
             .await
             .unwrap_or_else(|_| panic!("Error: Index read '{:?}' failed", index_path.as_os_str()));
-        let index: Index = toml::from_str(&index_content).unwrap_or_else(|err| {
+        let (index, corrupt) = parse_index(&index_content).unwrap_or_else(|err| {
             eprintln!("{:#?}", err);
             panic!("Error: Index parse failed")
         });
+        if !corrupt.is_empty() {
+            // the next rewrite drops the skipped entries, keep them for a manual repair
+            let backup = index_path.with_extension(format!(
+                "{}.toml.bak",
+                chrono::Local::now().format("%Y%m%d%H%M%S")
+            ));
+            std::fs::copy(&index_path, &backup).unwrap_or_else(|err| {
+                panic!("Error: Index backup to '{:?}' failed, {}", backup, err)
+            });
+            tracing::warn!(
+                "{} index entries failed to decode and are left out, the index was backed up to {:?}",
+                corrupt.len(),
+                backup
+            );
+        }
         let path = index_path.parent().unwrap().to_path_buf();
-        Self {
+        let bucket = Self {
             index: Arc::new(Mutex::new(index)),
-            index_file: index_file.into_std().await,
+            index_file: Mutex::new(index_file.into_std().await),
             path,
+            shard,
+            corrupt,
+        };
+        bucket
+            .migrate_layout()
+            .unwrap_or_else(|err| panic!("Error: Storage layout migration failed, {:?}", err));
+        bucket
+    }
+    /// Where the file of an entry with this id and extension is stored
+    pub(crate) fn resource_path(&self, uid: &Uuid, ext: Option<&str>) -> PathBuf {
+        let name = match ext {
+            Some(ext) => format!("{}.{}", uid, ext),
+            None => uid.to_string(),
+        };
+        self.path.join(shard_dirs(uid, &self.shard)).join(name)
+    }
+    /// Where the content uploaded as `filename` is stored, [`Bucket::write`] indexes it there
+    pub(crate) fn upload_path(&self, uid: &Uuid, filename: &Option<String>) -> PathBuf {
+        let ext = filename
+            .as_ref()
+            .map(Path::new)
+            .and_then(|it| it.extension())
+            .map(|it| it.to_string_lossy().to_string());
+        self.resource_path(uid, ext.as_deref())
+    }
+    pub(crate) fn get_resource_path(&self, entity: &BucketEntity) -> PathBuf {
+        self.resource_path(&entity.uid, entity.ext.as_deref())
+    }
+    /// Move files that aren't where the current shard setting expects them, e.g. after sharding
+    /// was turned on for a flat storage directory or its widths changed
+    fn migrate_layout(&self) -> anyhow::Result<()> {
+        let guard = self.index.lock().unwrap();
+        let mut files = Vec::new();
+        walk_files(&self.path, &mut files)?;
+        let files = files
+            .into_iter()
+            .filter_map(|it| Some((it.file_name()?.to_string_lossy().to_string(), it)))
+            .collect::<std::collections::HashMap<_, _>>();
+        let mut moved = 0;
+        for item in &guard.items {
+            let expected = self.get_resource_path(item);
+            let Some(current) = files.get(&item.get_resource()) else {
+                continue;
+            };
+            if *current == expected {
+                continue;
+            }
+            if let Some(parent) = expected.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(current, &expected).with_context(|| {
+                format!("Error: Move '{:?}' to '{:?}' failed", current, expected)
+            })?;
+            moved += 1;
+        }
+        if moved > 0 {
+            tracing::info!("Moved {} files into the configured storage layout", moved);
         }
+        Ok(())
     }
     /// Get BucketEntity
     pub(crate) fn get(&self, id: &Uuid) -> Option<BucketEntity> {
@@ -187,6 +399,15 @@ impl Bucket {
         }
         None
     }
+    /// Find an entry whose content likely equals a file with this probe and size
+    pub(crate) fn find_by_probe(&self, probe: &str, size: u64) -> Option<Uuid> {
+        let guard = self.index.lock().unwrap();
+        guard
+            .items
+            .iter()
+            .find(|it| it.size == size && it.probe.as_deref() == Some(probe))
+            .map(|it| it.uid)
+    }
     pub(crate) fn map_clone<T, F>(&self, f: F) -> Vec<T>
     where
         F: FnOnce(&Vec<BucketEntity>) -> Vec<T>,
@@ -194,12 +415,20 @@ impl Bucket {
         let guard = self.index.lock().unwrap();
         f(&guard.items)
     }
-    pub(crate) async fn delete(&self, id: &Uuid) -> anyhow::Result<()> {
+    /// Delete the entry and its file if `precondition` holds for the current entry, checked under
+    /// the index lock. Returns whether it held.
+    pub(crate) async fn delete(
+        &self,
+        id: &Uuid,
+        precondition: impl FnOnce(Option<&BucketEntity>) -> bool,
+    ) -> anyhow::Result<bool> {
         let mut guard = self.index.lock().unwrap();
+        if !precondition(guard.items.iter().find(|it| &it.uid == id)) {
+            return Ok(false);
+        }
         if let Some(idx) = guard.items.iter().position(|it| &it.uid == id) {
             let entity = guard.items.remove(idx);
-            let is_empty = guard.items.is_empty();
-            let resource_path = self.get_storage_path().join(entity.get_resource());
+            let resource_path = self.get_resource_path(&entity);
             if resource_path.exists() {
                 let result = std::fs::remove_file(&resource_path).with_context(|| {
                     format!("Error: Remove resource file '{:?}' failed", &resource_path)
@@ -210,25 +439,166 @@ impl Bucket {
                     return Err(err);
                 }
             };
-            let mut file = self.index_file.try_clone()?;
-            file.seek(SeekFrom::Start(0))?;
-            // Regenerate index file content
-            let content = if is_empty {
-                "".to_string()
-            } else {
-                toml::to_string(&*guard).unwrap()
-            };
-            let bytes = content.as_bytes();
-            // `write_all` is used to overwrite not truncate, so set the length here to ensure that all content is overwritten
-            file.set_len(bytes.len() as u64)?;
-            file.write_all(bytes)
-                .with_context(|| "Fatal error: Update index file failed")
-                .and_then(|_| self.sync_all())?
+            self.rewrite_index(&guard)?
         }
+        Ok(true)
+    }
+    /// Regenerate the whole index file from `index`
+    fn rewrite_index(&self, index: &Index) -> anyhow::Result<()> {
+        let content = if index.items.is_empty() {
+            "".to_string()
+        } else {
+            toml::to_string(index)?
+        };
+        let index_path = self.path.join("index.toml");
+        let mut file = self.index_file.lock().unwrap();
+        replace_file(&index_path, content.as_bytes())
+            .with_context(|| "Fatal error: Update index file failed")?;
+        // the old handle still points at the replaced file
+        *file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&index_path)
+            .with_context(|| "Fatal error: Reopen index file failed")?;
         Ok(())
     }
-    pub(crate) fn get_storage_path(&self) -> &PathBuf {
-        &self.path
+    /// Compare the index with the files in the storage directory.
+    ///
+    /// Reports files no entry refers to, entries whose file is gone and entries whose size
+    /// differs from the file. With `fix`, orphaned files are deleted, dangling entries are
+    /// removed and the size and hash of inconsistent entries are recomputed from the file.
+    ///
+    /// Files touched within the last [`ORPHAN_GRACE_PERIOD`] are not reported, they most likely
+    /// belong to an upload in progress which is indexed once complete.
+    ///
+    /// The storage is walked and hashed against a snapshot of the index, the index is only
+    /// locked to apply the fixes. Entries changed in the meantime are left alone and dropped
+    /// from the report, as are files removed during the walk.
+    ///
+    /// This is blocking work, run it off the async workers.
+    pub(crate) fn reconcile(&self, fix: bool) -> anyhow::Result<ReconcileReport> {
+        let is_gone = |err: &std::io::Error| err.kind() == std::io::ErrorKind::NotFound;
+        let items = self.index.lock().unwrap().items.clone();
+        let known = items
+            .iter()
+            .map(|it| self.get_resource_path(it))
+            .collect::<std::collections::HashSet<_>>();
+        let now = std::time::SystemTime::now();
+        let mut files = Vec::new();
+        walk_files(&self.path, &mut files)?;
+        let mut orphans = Vec::new();
+        for file in files {
+            let Some(name) = file.file_name().map(|it| it.to_string_lossy().to_string()) else {
+                continue;
+            };
+            let stem = name.split_once('.').map_or(name.as_str(), |(stem, _)| stem);
+            let Ok(uid) = Uuid::parse_str(stem) else {
+                continue;
+            };
+            let modified = match std::fs::metadata(&file).and_then(|it| it.modified()) {
+                Ok(modified) => modified,
+                Err(err) if is_gone(&err) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let age = now.duration_since(modified).unwrap_or_default();
+            // the file of an entry that failed to decode isn't an orphan, only its entry is lost
+            if age < ORPHAN_GRACE_PERIOD
+                || self.corrupt.contains(&uid.to_string())
+                || known.contains(&file)
+            {
+                continue;
+            }
+            orphans.push((name, file));
+        }
+        let mut dangling = Vec::new();
+        let mut inconsistent = Vec::new();
+        for item in &items {
+            let path = self.get_resource_path(item);
+            let size = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(err) if is_gone(&err) => {
+                    dangling.push(item.clone());
+                    continue;
+                }
+                Err(err) => {
+                    tracing::warn!(%err, "Skipping entry {}, its file can't be read", item.uid);
+                    continue;
+                }
+            };
+            if size == item.size {
+                continue;
+            }
+            if !fix {
+                inconsistent.push((item.clone(), None));
+                continue;
+            }
+            match rehash(item, &path) {
+                Ok(fixed) => inconsistent.push((item.clone(), Some(fixed))),
+                Err(err) if is_gone(&err) => dangling.push(item.clone()),
+                Err(err) => {
+                    tracing::warn!(%err, "Skipping entry {}, its file can't be read", item.uid)
+                }
+            }
+        }
+        if fix {
+            let mut guard = self.index.lock().unwrap();
+            let mut changed = false;
+            // an entry may have been added for the file since the snapshot
+            orphans.retain(|(_, file)| {
+                !guard
+                    .items
+                    .iter()
+                    .any(|it| self.get_resource_path(it) == *file)
+            });
+            for (name, file) in &orphans {
+                match std::fs::remove_file(file) {
+                    Err(err) if !is_gone(&err) => {
+                        return Err(anyhow::Error::from(err)
+                            .context(format!("Error: Remove orphaned file '{}' failed", name)));
+                    }
+                    _ => {}
+                }
+            }
+            dangling.retain(|snapshot| {
+                let Some(idx) = guard.items.iter().position(|it| it.uid == snapshot.uid) else {
+                    return false;
+                };
+                if !is_unchanged(&guard.items[idx], snapshot)
+                    || self.get_resource_path(snapshot).exists()
+                {
+                    return false;
+                }
+                guard.items.remove(idx);
+                changed = true;
+                true
+            });
+            inconsistent.retain_mut(|(snapshot, fixed)| {
+                let Some(item) = guard.items.iter_mut().find(|it| it.uid == snapshot.uid) else {
+                    return false;
+                };
+                if !is_unchanged(item, snapshot) {
+                    return false;
+                }
+                if let Some(fixed) = fixed.take() {
+                    *item = fixed;
+                    changed = true;
+                }
+                true
+            });
+            if changed {
+                self.rewrite_index(&guard)?;
+            }
+        }
+        Ok(ReconcileReport {
+            orphaned_files: orphans.into_iter().map(|(name, _)| name).collect(),
+            dangling_entries: dangling.iter().map(|it| it.uid).collect(),
+            inconsistent_entries: inconsistent.iter().map(|(it, _)| it.uid).collect(),
+            corrupt_entries: self.find_corrupt().to_vec(),
+        })
+    }
+    /// Index entries that failed to decode at startup, by uid when readable
+    pub(crate) fn find_corrupt(&self) -> &[String] {
+        &self.corrupt
     }
     /// Writing entity to index file
     async fn write_index(&self, entity: &BucketEntity) -> anyhow::Result<()> {
@@ -238,19 +608,24 @@ impl Bucket {
             newline = if is_empty { "" } else { "\n" },
             body = toml::to_string(entity)?
         );
-        let mut file = self.index_file.try_clone()?;
+        let mut file = self.index_file.lock().unwrap();
         file.seek(SeekFrom::End(0))?;
         file.write_all(part.as_bytes())
             .with_context(|| "Fatal Error: Write new index to index file failed")?;
-        self.sync_all()?;
-        Ok(())
-    }
-    /// Sync indexes to index file
-    fn sync_all(&self) -> anyhow::Result<()> {
-        self.index_file
-            .sync_all()
+        file.sync_all()
             .with_context(|| "Fatal Error: Sync indexes to file failed")
     }
+    /// Whether `size` more bytes fit on the storage volume leaving `reserved` bytes free. Always
+    /// when no reserve is configured or the free space can't be read
+    pub(crate) fn has_room(&self, size: u64, reserved: Option<u64>) -> bool {
+        let Some(reserved) = reserved else {
+            return true;
+        };
+        match utils::available_space(&self.path) {
+            Some(available) => available.saturating_sub(reserved) >= size,
+            None => true,
+        }
+    }
     /// Pre-allocate a UUID and file with the option to pre-size.
     ///
     /// # Params
@@ -265,20 +640,14 @@ impl Bucket {
         size: &Option<u64>,
     ) -> anyhow::Result<PreallocationFile> {
         let uid = Uuid::new_v4();
-        let ext = filename
-            .as_ref()
-            .map(Path::new)
-            .and_then(|it| it.extension())
-            .map(|it| it.to_string_lossy().to_string());
-        let path = self.path.join({
-            match ext {
-                Some(ext) => format!("{}.{}", uid, ext),
-                None => uid.to_string(),
-            }
-        });
+        let path = self.upload_path(&uid, filename);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
         let file = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&path)
             .await?;
         if let Some(size) = size {
@@ -308,12 +677,15 @@ impl Bucket {
         } else {
             (format!("pasted_{}", now.format("%Y-%m-%d-%H-%M")), None)
         };
+        let path = self.resource_path(&uid, ext.as_deref());
+        let probe = tokio::task::spawn_blocking(move || utils::probe_hash(&path)).await??;
         let item = BucketEntity {
             uid,
             name,
             created: now.timestamp_millis(),
             modified: None,
             hash,
+            probe: Some(probe),
             size: size as u64,
             r#type,
             ext,
@@ -325,6 +697,29 @@ impl Bucket {
     }
 }
 
+#[cfg(test)]
+impl Bucket {
+    /// Store and index `content` as a `text/plain` file named `name`, hashed with SHA-256
+    pub(crate) async fn store_test_file(&self, name: &str, content: &[u8]) -> Uuid {
+        let filename = Some(name.to_string());
+        let preallocation = self.preallocation(&filename, &None).await.unwrap();
+        fs::write(&preallocation.path, content).await.unwrap();
+        let mut hasher = utils::HashAlgorithm::Sha256.hasher();
+        hasher.update(content);
+        self.write(
+            preallocation.uid,
+            None,
+            filename,
+            "text/plain".to_string(),
+            hasher.finalize(),
+            content.len(),
+        )
+        .await
+        .unwrap();
+        preallocation.uid
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum BucketAction {
     Add(Uuid),
@@ -354,3 +749,141 @@ impl Display for BucketAction {
         write!(f, "[{}]@{}", action, uid)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_dirs() {
+        let uid = Uuid::parse_str("ab12cd34-0000-0000-0000-000000000000").unwrap();
+        assert_eq!(shard_dirs(&uid, &[]), PathBuf::new());
+        assert_eq!(shard_dirs(&uid, &[2, 2]), Path::new("ab").join("12"));
+        assert_eq!(shard_dirs(&uid, &[1, 3]), Path::new("a").join("b12"));
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let content = r#"
+[[item]]
+uid = "88511c4a-a167-40f0-ab84-b837acd32015"
+created = "2026-10-16 18:34:59 UTC"
+name = "f.txt"
+hash = "496ef9713efabd24d776ab8ca451a89f17d215014fa3ead54ee5bcd0c697d7ea"
+size = 8899
+type = "text/plain"
+ext = "txt"
+
+[[item]]
+uid = "0dfe8ccc-45c6-44ae-91f4-655af2df4a65"
+created = "not a date"
+name = "a.txt"
+hash = "4a60bf7d4bc1e485744cf7e8d0860524752fca1ce42331be7c439fd23043f151"
+size = 2
+type = "text/plain"
+
+[[item]]
+name = "b.txt"
+"#;
+        let (index, corrupt) = parse_index(content).unwrap();
+        assert_eq!(index.items.len(), 1);
+        assert_eq!(index.items[0].get_name(), "f.txt");
+        assert_eq!(
+            corrupt,
+            [
+                "0dfe8ccc-45c6-44ae-91f4-655af2df4a65".to_string(),
+                "#2".to_string()
+            ]
+        );
+        assert!(parse_index("").unwrap().0.items.is_empty());
+        // a file that isn't TOML at all is still fatal
+        assert!(parse_index("[[item]\n").is_err());
+    }
+
+    #[test]
+    fn test_replace_file() {
+        let dir = std::env::temp_dir().join(format!("synclink-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("index.toml");
+        replace_file(&path, b"old").unwrap();
+        // a crash halfway through writing only ever leaves a partial temp file behind
+        std::fs::write(dir.join("index.toml.tmp"), b"ne").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        replace_file(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!dir.join("index.toml.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let dir = std::env::temp_dir().join(format!("synclink-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bucket = Bucket::connect(&dir, Vec::new()).await;
+        let sha256 = |bytes: &[u8]| {
+            let mut hasher = utils::HashAlgorithm::Sha256.hasher();
+            hasher.update(bytes);
+            hasher.finalize()
+        };
+        let store = |uid: &Uuid, content: &[u8]| {
+            std::fs::write(bucket.resource_path(uid, Some("txt")), content).unwrap();
+        };
+        // consistent
+        let kept = bucket.store_test_file("a.txt", b"kept").await;
+        // file removed after indexing
+        let dangling = bucket.store_test_file("a.txt", b"gone").await;
+        std::fs::remove_file(bucket.resource_path(&dangling, Some("txt"))).unwrap();
+        // file grown after indexing
+        let drifted = bucket.store_test_file("a.txt", b"hel").await;
+        store(&drifted, b"hello");
+        // no entry, old enough not to be an upload in progress
+        let orphan = Uuid::new_v4();
+        store(&orphan, b"orphan");
+        let hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(bucket.resource_path(&orphan, Some("txt")))
+            .unwrap()
+            .set_modified(hour_ago)
+            .unwrap();
+        // no entry yet, an upload in progress
+        let uploading = Uuid::new_v4();
+        store(&uploading, b"uploading");
+
+        let report = bucket.reconcile(false).unwrap();
+        assert_eq!(report.orphaned_files, [format!("{}.txt", orphan)]);
+        assert_eq!(report.dangling_entries, [dangling]);
+        assert_eq!(report.inconsistent_entries, [drifted]);
+        // a dry run changes nothing
+        assert!(bucket.resource_path(&orphan, Some("txt")).exists());
+        assert!(bucket.has(&dangling));
+        assert_eq!(*bucket.get(&drifted).unwrap().get_size(), 3);
+
+        let report = bucket.reconcile(true).unwrap();
+        assert_eq!(report.orphaned_files, [format!("{}.txt", orphan)]);
+        assert_eq!(report.dangling_entries, [dangling]);
+        assert_eq!(report.inconsistent_entries, [drifted]);
+        assert!(!bucket.resource_path(&orphan, Some("txt")).exists());
+        assert!(bucket.resource_path(&uploading, Some("txt")).exists());
+        assert!(!bucket.has(&dangling));
+        assert!(bucket.has(&kept));
+        let fixed = bucket.get(&drifted).unwrap();
+        assert_eq!(*fixed.get_size(), 5);
+        assert_eq!(fixed.get_hash(), sha256(b"hello"));
+        assert!(fixed.get_modified().is_some());
+        // the fixes are persisted
+        let content = std::fs::read_to_string(dir.join("index.toml")).unwrap();
+        let (persisted, _) = parse_index(&content).unwrap();
+        assert_eq!(persisted.items.len(), 2);
+        assert!(persisted
+            .items
+            .iter()
+            .any(|it| it.uid == drifted && it.size == 5));
+
+        let report = bucket.reconcile(true).unwrap();
+        assert!(report.orphaned_files.is_empty());
+        assert!(report.dangling_entries.is_empty());
+        assert!(report.inconsistent_entries.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
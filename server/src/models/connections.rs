@@ -0,0 +1,130 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct Connection {
+    connected_since: i64,
+    addr: SocketAddr,
+    forwarded_for: Option<String>,
+    user_agent: String,
+    delivered: Arc<AtomicU64>,
+}
+
+/// Snapshot of a connected SSE subscriber
+#[derive(Serialize, Debug)]
+pub struct ConnectionDto {
+    id: u64,
+    /// milliseconds since the epoch
+    connected_since: i64,
+    /// peer address of the socket
+    ip: String,
+    /// `X-Forwarded-For` as sent, when behind a proxy
+    forwarded_for: Option<String>,
+    user_agent: String,
+    /// number of events sent, replayed ones included
+    delivered: u64,
+}
+
+/// Registry of the SSE subscribers, for spotting clients that never close their stream
+#[derive(Default)]
+pub(crate) struct Connections {
+    next_id: AtomicU64,
+    connections: Mutex<HashMap<u64, Connection>>,
+}
+
+/// Unregisters the connection when the stream is dropped, and counts delivered events
+/// without touching the registry lock
+pub(crate) struct ConnectionGuard {
+    id: u64,
+    delivered: Arc<AtomicU64>,
+    connections: Arc<Connections>,
+}
+
+impl ConnectionGuard {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+    pub(crate) fn delivered(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections
+            .connections
+            .lock()
+            .unwrap()
+            .remove(&self.id);
+    }
+}
+
+impl Connections {
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        addr: SocketAddr,
+        forwarded_for: Option<String>,
+        user_agent: String,
+    ) -> ConnectionGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let delivered = Arc::new(AtomicU64::new(0));
+        self.connections.lock().unwrap().insert(
+            id,
+            Connection {
+                connected_since: chrono::Utc::now().timestamp_millis(),
+                addr,
+                forwarded_for,
+                user_agent,
+                delivered: delivered.clone(),
+            },
+        );
+        ConnectionGuard {
+            id,
+            delivered,
+            connections: self.clone(),
+        }
+    }
+    /// Copy the connections out, the lock is only held for the copy
+    pub(crate) fn snapshot(&self) -> Vec<ConnectionDto> {
+        let mut connections = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, it)| ConnectionDto {
+                id: *id,
+                connected_since: it.connected_since,
+                ip: it.addr.ip().to_string(),
+                forwarded_for: it.forwarded_for.clone(),
+                user_agent: it.user_agent.clone(),
+                delivered: it.delivered.load(Ordering::Relaxed),
+            })
+            .collect::<Vec<_>>();
+        connections.sort_by_key(|it| it.id);
+        connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        let connections = Arc::new(Connections::default());
+        let addr: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        let first = connections.register(addr, None, "a".to_string());
+        let second = connections.register(addr, None, "b".to_string());
+        second.delivered();
+        second.delivered();
+        let snapshot = connections.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[1].delivered, 2);
+        drop(first);
+        let snapshot = connections.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].id, second.id());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::models::bucket::BucketAction;
+use crate::models::notifier::NotifyEvent;
+use crate::models::Bucket;
+use crate::utils;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Tree of symlinks named after the original filenames and pointing at the stored files, so
+/// the storage directory can be browsed or backed up by a human.
+///
+/// The mirror is derived state. It is rebuilt at startup and follows the notify channel
+/// afterwards, storing and serving files never reads it.
+pub(crate) struct Mirror {
+    dir: PathBuf,
+    bucket: Arc<Bucket>,
+}
+
+impl Mirror {
+    pub(crate) fn new(dir: PathBuf, bucket: Arc<Bucket>) -> Self {
+        Self { dir, bucket }
+    }
+    /// Rebuild the mirror and keep it up to date until the notify channel closes.
+    ///
+    /// Nothing is spawned when symlinks can't be created in the mirror directory.
+    pub(crate) fn spawn(self, mut receiver: broadcast::Receiver<NotifyEvent>) {
+        use broadcast::error::RecvError;
+        if let Err(err) = self.probe() {
+            tracing::warn!(%err, "Mirror disabled, symlinks can't be created in {:?}", self.dir);
+            return;
+        }
+        let mirror = Arc::new(self);
+        tokio::spawn(async move {
+            Self::resync(&mirror).await;
+            loop {
+                let action = match receiver.recv().await {
+                    Ok((_, action)) => action,
+                    // the skipped events are unknown, rebuild from the index instead
+                    Err(RecvError::Lagged(count)) => {
+                        tracing::warn!("mirror lagged, {} events skipped, resyncing", count);
+                        Self::resync(&mirror).await;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let mirror = mirror.clone();
+                let result = tokio::task::spawn_blocking(move || match action {
+                    BucketAction::Add(uid) => mirror.link(&uid),
+                    BucketAction::Delete(uid) => mirror.unlink(&uid),
+                })
+                .await;
+                if let Ok(Err(err)) = result {
+                    tracing::warn!(%err, "Failed to update mirror");
+                }
+            }
+        });
+    }
+    /// Run [`Mirror::sync`] on the blocking pool
+    async fn resync(mirror: &Arc<Self>) {
+        let sync = mirror.clone();
+        match tokio::task::spawn_blocking(move || sync.sync()).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(count)) => tracing::info!("Mirror synced, {} links created", count),
+            Ok(Err(err)) => tracing::warn!(%err, "Failed to sync mirror"),
+            Err(err) => tracing::warn!(%err, "Mirror sync panicked"),
+        }
+    }
+    fn probe(&self) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let link = self.dir.join(".synclink-probe");
+        let _ = std::fs::remove_file(&link);
+        symlink(&self.dir, &link)?;
+        std::fs::remove_file(&link)
+    }
+    /// Drop links to files that are gone or moved, then link the stored files that have no
+    /// link. Returns the number of links created.
+    fn sync(&self) -> std::io::Result<usize> {
+        let mut linked = HashSet::new();
+        for (path, target) in self.links()? {
+            let current = link_uid(&target).and_then(|uid| {
+                let entity = self.bucket.get(&uid)?;
+                (self.bucket.get_resource_path(&entity) == target).then_some(uid)
+            });
+            match current {
+                Some(uid) => {
+                    linked.insert(uid);
+                }
+                None => std::fs::remove_file(&path)?,
+            }
+        }
+        let missing = self.bucket.map_clone(|items| {
+            items
+                .iter()
+                .map(|it| *it.get_uid())
+                .filter(|uid| !linked.contains(uid))
+                .collect()
+        });
+        for uid in &missing {
+            self.link(uid)?;
+        }
+        Ok(missing.len())
+    }
+    fn link(&self, uid: &Uuid) -> std::io::Result<()> {
+        let Some(entity) = self.bucket.get(uid) else {
+            return Ok(());
+        };
+        let name = entity.get_name();
+        let mut path = self.dir.join(name);
+        let mut n = 1;
+        while path.symlink_metadata().is_ok() {
+            path = self.dir.join(utils::numbered_name(name, n));
+            n += 1;
+        }
+        symlink(&self.bucket.get_resource_path(&entity), &path)
+    }
+    fn unlink(&self, uid: &Uuid) -> std::io::Result<()> {
+        for (path, target) in self.links()? {
+            if link_uid(&target).as_ref() == Some(uid) {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+    /// Symlinks in the mirror directory along with their targets
+    fn links(&self) -> std::io::Result<Vec<(PathBuf, PathBuf)>> {
+        let mut links = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_symlink() {
+                let target = std::fs::read_link(entry.path())?;
+                links.push((entry.path(), target));
+            }
+        }
+        Ok(links)
+    }
+}
+
+/// Id of the stored file a link points at, stored files are named `<uid>` or `<uid>.<ext>`
+fn link_uid(target: &Path) -> Option<Uuid> {
+    let name = target.file_name()?.to_str()?;
+    Uuid::parse_str(name.split('.').next()?).ok()
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_uid() {
+        let uid = Uuid::parse_str("88511c4a-a167-40f0-ab84-b837acd32015").unwrap();
+        let storage = Path::new("/srv/storage/88/51");
+        assert_eq!(
+            link_uid(&storage.join("88511c4a-a167-40f0-ab84-b837acd32015.txt")),
+            Some(uid)
+        );
+        assert_eq!(
+            link_uid(&storage.join("88511c4a-a167-40f0-ab84-b837acd32015")),
+            Some(uid)
+        );
+        assert_eq!(link_uid(&storage.join("index.toml")), None);
+    }
+}
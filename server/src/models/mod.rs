@@ -1,3 +1,11 @@
 pub(crate) mod bucket;
+pub(crate) mod connections;
+pub(crate) mod mirror;
+pub(crate) mod notifier;
+pub(crate) mod upload_session;
+pub(crate) mod webhook;
 
 pub(crate) use bucket::Bucket;
+pub(crate) use connections::Connections;
+pub(crate) use notifier::Notifier;
+pub(crate) use upload_session::UploadSessions;
@@ -0,0 +1,220 @@
+use crate::models::bucket::BucketAction;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// A bucket action tagged with a monotonic event id
+pub type NotifyEvent = (u64, BucketAction);
+
+/// Broadcasts bucket actions to SSE subscribers and keeps a ring buffer of recent events,
+/// so a reconnecting client can replay what it missed via `Last-Event-ID`.
+///
+/// Delivery is at-least-once: a client may receive an event it has already seen, but will not
+/// miss one as long as the gap fits in the buffer. Event ids restart from 1 when the server
+/// restarts, an id the server has not issued yet replays the whole buffer.
+pub(crate) struct Notifier {
+    sender: broadcast::Sender<NotifyEvent>,
+    history: Mutex<History>,
+}
+
+/// Live side of a subscription, remembers the last event handed out so a lag can be
+/// caught up from the buffer by [`Notifier::recv`].
+pub(crate) struct Subscription {
+    pub(crate) receiver: broadcast::Receiver<NotifyEvent>,
+    last_id: u64,
+}
+
+struct History {
+    next_id: u64,
+    capacity: usize,
+    events: VecDeque<NotifyEvent>,
+}
+
+impl Notifier {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(8);
+        Self {
+            sender,
+            history: Mutex::new(History {
+                next_id: 1,
+                capacity,
+                events: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+    /// Assign an id to the action, record it and broadcast to the current subscribers
+    pub(crate) fn send(
+        &self,
+        action: BucketAction,
+    ) -> Result<usize, broadcast::error::SendError<NotifyEvent>> {
+        let mut history = self.history.lock().unwrap();
+        let event = (history.next_id, action);
+        history.next_id += 1;
+        if history.capacity > 0 {
+            if history.events.len() == history.capacity {
+                history.events.pop_front();
+            }
+            history.events.push_back(event.clone());
+        }
+        // send under the lock so that the buffer and the channel always agree on the order
+        self.sender.send(event)
+    }
+    /// Subscribe to the live events, returns buffered events newer than `last_id` as well.
+    ///
+    /// Subscribing and collecting the replay happen under the same lock, so no event falls
+    /// into the gap between them or shows up twice.
+    pub(crate) fn subscribe(&self, last_id: Option<u64>) -> (Vec<NotifyEvent>, Subscription) {
+        let history = self.history.lock().unwrap();
+        let subscription = Subscription {
+            receiver: self.sender.subscribe(),
+            last_id: history.next_id - 1,
+        };
+        let replay = match last_id {
+            Some(last_id) if last_id < history.next_id => history
+                .events
+                .iter()
+                .filter(|(id, _)| *id > last_id)
+                .cloned()
+                .collect(),
+            Some(_) => history.events.iter().cloned().collect(),
+            None => Vec::new(),
+        };
+        (replay, subscription)
+    }
+    /// Subscribe to the live events and capture `snapshot()` under the same lock, returns the
+    /// id of the latest event issued so far as well.
+    ///
+    /// Actions are applied to the bucket before they are sent, so everything up to that id is
+    /// reflected in the snapshot and everything after it arrives on the receiver. An action
+    /// applied but not sent yet shows up in both, which is harmless for ADD/DELETE.
+    pub(crate) fn subscribe_with_snapshot<T>(
+        &self,
+        snapshot: impl FnOnce() -> T,
+    ) -> (u64, T, Subscription) {
+        let history = self.history.lock().unwrap();
+        let subscription = Subscription {
+            receiver: self.sender.subscribe(),
+            last_id: history.next_id - 1,
+        };
+        (subscription.last_id, snapshot(), subscription)
+    }
+    /// Wait for the next events of the subscription, `None` once the channel is closed.
+    ///
+    /// A subscriber that fell behind the channel gets the events it missed from the buffer in
+    /// one batch, only a gap larger than the buffer is lost.
+    pub(crate) async fn recv(&self, subscription: &mut Subscription) -> Option<Vec<NotifyEvent>> {
+        loop {
+            match subscription.receiver.recv().await {
+                // already delivered by a replay
+                Ok((id, _)) if id <= subscription.last_id => continue,
+                Ok(event) => {
+                    subscription.last_id = event.0;
+                    return Some(vec![event]);
+                }
+                Err(RecvError::Lagged(_)) => {
+                    let history = self.history.lock().unwrap();
+                    let missed: Vec<_> = history
+                        .events
+                        .iter()
+                        .filter(|(id, _)| *id > subscription.last_id)
+                        .cloned()
+                        .collect();
+                    let first_id = missed.first().map_or(history.next_id, |(id, _)| *id);
+                    if first_id > subscription.last_id + 1 {
+                        tracing::warn!(
+                            "subscriber lagged, {} events older than the replay buffer skipped",
+                            first_id - subscription.last_id - 1
+                        );
+                    }
+                    subscription.last_id = history.next_id - 1;
+                    if !missed.is_empty() {
+                        return Some(missed);
+                    }
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn ids(events: &[NotifyEvent]) -> Vec<u64> {
+        events.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn test_replay() {
+        let notifier = Notifier::new(3);
+        for _ in 0..5 {
+            let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        }
+        assert_eq!(ids(&notifier.subscribe(None).0), Vec::<u64>::new());
+        assert_eq!(ids(&notifier.subscribe(Some(3)).0), vec![4, 5]);
+        assert_eq!(ids(&notifier.subscribe(Some(5)).0), Vec::<u64>::new());
+        // older than the buffer, replay whatever is left
+        assert_eq!(ids(&notifier.subscribe(Some(1)).0), vec![3, 4, 5]);
+        // issued by a previous process
+        assert_eq!(ids(&notifier.subscribe(Some(42)).0), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_subscribe_with_snapshot() {
+        let notifier = Notifier::new(3);
+        let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        let (last_id, snapshot, mut subscription) = notifier.subscribe_with_snapshot(|| "state");
+        assert_eq!((last_id, snapshot), (1, "state"));
+        let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        assert_eq!(subscription.receiver.try_recv().unwrap().0, 2);
+    }
+
+    #[tokio::test]
+    async fn test_recv_lagged() {
+        let notifier = Notifier::new(64);
+        let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        let (_, mut subscription) = notifier.subscribe(None);
+        // overflows the channel, the first events are only left in the buffer
+        for _ in 0..20 {
+            let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        }
+        let mut received = Vec::new();
+        while received.len() < 20 {
+            received.extend(ids(&notifier.recv(&mut subscription).await.unwrap()));
+        }
+        assert_eq!(received, (2..=21).collect::<Vec<_>>());
+        let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        assert_eq!(
+            ids(&notifier.recv(&mut subscription).await.unwrap()),
+            vec![22]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recv_lagged_beyond_buffer() {
+        let notifier = Notifier::new(4);
+        let (_, mut subscription) = notifier.subscribe(None);
+        for _ in 0..20 {
+            let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        }
+        // whatever the buffer still holds, nothing twice
+        assert_eq!(
+            ids(&notifier.recv(&mut subscription).await.unwrap()),
+            vec![17, 18, 19, 20]
+        );
+        let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        assert_eq!(
+            ids(&notifier.recv(&mut subscription).await.unwrap()),
+            vec![21]
+        );
+    }
+
+    #[test]
+    fn test_no_buffer() {
+        let notifier = Notifier::new(0);
+        let _ = notifier.send(BucketAction::Add(Uuid::new_v4()));
+        assert!(notifier.subscribe(Some(0)).0.is_empty());
+    }
+}
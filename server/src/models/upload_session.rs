@@ -0,0 +1,298 @@
+use crate::utils;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
+
+/// Directory holding the `{uid}.part.{pos}` files of `upload-part`
+pub(crate) fn parts_dir() -> PathBuf {
+    std::env::temp_dir().join("synclink")
+}
+
+/// A resumable upload allocated through `upload-part`
+struct UploadSession {
+    hash: String,
+    /// size of each part
+    parts: Vec<u64>,
+    /// whether each part has been appended completely
+    received: Vec<bool>,
+    /// address the session was allocated from
+    client: IpAddr,
+    /// when the session was allocated or last appended to
+    touched: Instant,
+}
+
+impl UploadSession {
+    fn total(&self) -> u64 {
+        self.parts.iter().sum()
+    }
+    /// bytes covered by the leading run of received parts
+    fn resume_offset(&self) -> u64 {
+        self.parts
+            .iter()
+            .zip(self.received.iter())
+            .take_while(|(_, received)| **received)
+            .map(|(size, _)| size)
+            .sum()
+    }
+}
+
+/// How long an interrupted single-shot upload can be resumed. Shorter than the grace period
+/// `reconcile` gives unindexed files, so it never reports the partial file as orphaned.
+pub(crate) const RESUME_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A single-shot `upload` whose body ended early, kept so a retry can continue where it stopped
+pub(crate) struct InterruptedUpload {
+    pub(crate) uid: Uuid,
+    pub(crate) path: PathBuf,
+    /// hash state over the bytes written so far
+    pub(crate) hasher: utils::ContentHasher,
+    pub(crate) written: u64,
+    /// length of the whole content
+    pub(crate) total: u64,
+    interrupted_at: Instant,
+}
+
+impl InterruptedUpload {
+    pub(crate) fn new(uid: Uuid, path: PathBuf, hasher: utils::ContentHasher, total: u64) -> Self {
+        Self {
+            uid,
+            path,
+            hasher,
+            written: 0,
+            total,
+            interrupted_at: Instant::now(),
+        }
+    }
+    fn is_expired(&self) -> bool {
+        self.interrupted_at.elapsed() >= RESUME_TTL
+    }
+}
+
+/// In-memory registry of the resumable uploads in progress, so a client can find out it
+/// already started uploading the same content.
+#[derive(Default)]
+pub(crate) struct UploadSessions {
+    sessions: Mutex<HashMap<Uuid, UploadSession>>,
+    /// interrupted single-shot uploads by content hash
+    interrupted: Mutex<HashMap<String, InterruptedUpload>>,
+}
+
+impl UploadSessions {
+    /// Register a session allocated from `client`, unless the client already has `limit`
+    /// sessions open. Returns whether it was registered.
+    pub(crate) fn create(
+        &self,
+        uid: Uuid,
+        hash: String,
+        parts: Vec<u64>,
+        client: IpAddr,
+        limit: Option<usize>,
+    ) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        let open = sessions.values().filter(|it| it.client == client).count();
+        if limit.is_some_and(|limit| open >= limit) {
+            return false;
+        }
+        let received = vec![false; parts.len()];
+        sessions.insert(
+            uid,
+            UploadSession {
+                hash,
+                parts,
+                received,
+                client,
+                touched: Instant::now(),
+            },
+        );
+        true
+    }
+    pub(crate) fn mark_received(&self, uid: &Uuid, pos: u32) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(uid) {
+            session.touched = Instant::now();
+            if let Some(received) = session.received.get_mut(pos as usize) {
+                *received = true;
+            }
+        }
+    }
+    pub(crate) fn remove(&self, uid: &Uuid) {
+        self.sessions.lock().unwrap().remove(uid);
+    }
+    /// Find a session uploading the content with `hash`, returns its id and resume offset.
+    ///
+    /// When `size` is given the session must cover exactly that many bytes.
+    pub(crate) fn find(&self, hash: &str, size: Option<u64>) -> Option<(Uuid, u64)> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.iter().find_map(|(uid, session)| {
+            if session.hash == hash && size.is_none_or(|size| size == session.total()) {
+                Some((*uid, session.resume_offset()))
+            } else {
+                None
+            }
+        })
+    }
+    /// Keep an interrupted upload for [`RESUME_TTL`], returns the upload of the same content
+    /// it replaces, whose file is no longer needed
+    pub(crate) fn interrupt(
+        &self,
+        hash: String,
+        mut upload: InterruptedUpload,
+    ) -> Option<InterruptedUpload> {
+        upload.interrupted_at = Instant::now();
+        self.interrupted.lock().unwrap().insert(hash, upload)
+    }
+    /// Take the interrupted upload of the content with `hash` to continue it
+    pub(crate) fn take_interrupted(&self, hash: &str) -> Option<InterruptedUpload> {
+        let mut interrupted = self.interrupted.lock().unwrap();
+        if interrupted.get(hash)?.is_expired() {
+            return None;
+        }
+        interrupted.remove(hash)
+    }
+    /// Put back an upload taken with [`Self::take_interrupted`] that couldn't be continued,
+    /// it expires as if it had never been taken
+    pub(crate) fn restore_interrupted(&self, hash: String, upload: InterruptedUpload) {
+        self.interrupted
+            .lock()
+            .unwrap()
+            .entry(hash)
+            .or_insert(upload);
+    }
+    /// Remove the interrupted upload of `hash` once it outlived [`RESUME_TTL`]
+    pub(crate) fn expire_interrupted(&self, hash: &str) -> Option<InterruptedUpload> {
+        let mut interrupted = self.interrupted.lock().unwrap();
+        if !interrupted.get(hash)?.is_expired() {
+            return None;
+        }
+        interrupted.remove(hash)
+    }
+    /// Offset an interrupted upload of the content with `hash` can be resumed from.
+    ///
+    /// When `size` is given the upload must cover exactly that many bytes.
+    pub(crate) fn interrupted_offset(&self, hash: &str, size: Option<u64>) -> Option<u64> {
+        let interrupted = self.interrupted.lock().unwrap();
+        let upload = interrupted.get(hash)?;
+        (!upload.is_expired() && size.is_none_or(|size| size == upload.total))
+            .then_some(upload.written)
+    }
+    /// Delete part files older than `max_age` that belong to no session, e.g. left behind by
+    /// a crash or a client that never concatenated. Returns the number of files and bytes removed.
+    ///
+    /// Sessions untouched for `max_age` are abandoned, they are dropped first so their files go
+    /// in the same sweep and they no longer count against their client's limit.
+    ///
+    /// The registry stays locked for the whole sweep, so a session can't be created or
+    /// appended to in between the check and the deletion. Files of a session that is still
+    /// being allocated are younger than `max_age` and left alone.
+    pub(crate) fn sweep(&self, dir: &Path, max_age: Duration) -> std::io::Result<(usize, u64)> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, it| it.touched.elapsed() < max_age);
+        let now = SystemTime::now();
+        let (mut files, mut bytes) = (0, 0);
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let Some(uid) = part_file_uid(&entry.file_name().to_string_lossy()) else {
+                continue;
+            };
+            if sessions.contains_key(&uid) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+            if !metadata.is_file() || age < max_age {
+                continue;
+            }
+            std::fs::remove_file(entry.path())?;
+            files += 1;
+            bytes += metadata.len();
+        }
+        Ok((files, bytes))
+    }
+}
+
+/// Parse the session id out of a `{uid}.part.{pos}` file name
+fn part_file_uid(name: &str) -> Option<Uuid> {
+    let (uid, pos) = name.split_once(".part.")?;
+    pos.parse::<u32>().ok()?;
+    Uuid::parse_str(uid).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn test_find() {
+        let sessions = UploadSessions::default();
+        let uid = Uuid::new_v4();
+        assert!(sessions.create(uid, "abc".to_string(), vec![10, 10, 5], CLIENT, None));
+        assert_eq!(sessions.find("abc", None), Some((uid, 0)));
+        sessions.mark_received(&uid, 1);
+        // part 0 is still missing
+        assert_eq!(sessions.find("abc", Some(25)), Some((uid, 0)));
+        sessions.mark_received(&uid, 0);
+        assert_eq!(sessions.find("abc", Some(25)), Some((uid, 20)));
+        assert_eq!(sessions.find("abc", Some(24)), None);
+        assert_eq!(sessions.find("xyz", None), None);
+        sessions.remove(&uid);
+        assert_eq!(sessions.find("abc", None), None);
+    }
+
+    #[test]
+    fn test_session_limit() {
+        let sessions = UploadSessions::default();
+        let create =
+            |client| sessions.create(Uuid::new_v4(), "abc".to_string(), vec![1], client, Some(2));
+        let first = Uuid::new_v4();
+        assert!(sessions.create(first, "abc".to_string(), vec![1], CLIENT, Some(2)));
+        assert!(create(CLIENT));
+        assert!(!create(CLIENT));
+        // other clients have their own allowance
+        assert!(create(IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)));
+        sessions.remove(&first);
+        assert!(create(CLIENT));
+        assert!(!create(CLIENT));
+        // abandoned sessions are dropped by the sweep
+        let dir = std::env::temp_dir().join(format!("synclink-sweep-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        sessions.sweep(&dir, Duration::ZERO).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(create(CLIENT));
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let sessions = UploadSessions::default();
+        let uid = Uuid::new_v4();
+        let mut upload = InterruptedUpload::new(
+            uid,
+            PathBuf::from("a.bin"),
+            utils::HashAlgorithm::Sha256.hasher(),
+            25,
+        );
+        upload.written = 10;
+        assert!(sessions.interrupt("abc".to_string(), upload).is_none());
+        assert_eq!(sessions.interrupted_offset("abc", Some(25)), Some(10));
+        assert_eq!(sessions.interrupted_offset("abc", Some(24)), None);
+        // not expired yet
+        assert!(sessions.expire_interrupted("abc").is_none());
+        let upload = sessions.take_interrupted("abc").unwrap();
+        assert_eq!(upload.uid, uid);
+        assert_eq!(sessions.interrupted_offset("abc", None), None);
+        sessions.restore_interrupted("abc".to_string(), upload);
+        assert_eq!(sessions.interrupted_offset("abc", None), Some(10));
+    }
+
+    #[test]
+    fn test_part_file_uid() {
+        let uid = Uuid::new_v4();
+        assert_eq!(part_file_uid(&format!("{}.part.3", uid)), Some(uid));
+        assert_eq!(part_file_uid(&format!("{}.part.x", uid)), None);
+        assert_eq!(part_file_uid("index.toml"), None);
+        assert_eq!(part_file_uid("foo.part.1"), None);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::config::WebhookConfig;
+use crate::models::bucket::BucketAction;
+use crate::models::notifier::{Notifier, Subscription};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Deliver bucket actions to the configured webhook endpoints.
+///
+/// Each delivery runs in its own task with a request timeout and a bounded retry, so a slow
+/// or dead endpoint never backs up the notify channel.
+pub(crate) struct Webhook {
+    client: reqwest::Client,
+    config: WebhookConfig,
+}
+
+impl Webhook {
+    pub(crate) fn new(config: WebhookConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()?;
+        Ok(Self { client, config })
+    }
+    /// Listen to the notify channel until it closes, events missed by a lag are replayed
+    /// from the notifier buffer.
+    pub(crate) fn spawn(self, notifier: Arc<Notifier>, mut subscription: Subscription) {
+        let webhook = Arc::new(self);
+        tokio::spawn(async move {
+            while let Some(events) = notifier.recv(&mut subscription).await {
+                for (_, action) in events {
+                    let webhook = webhook.clone();
+                    tokio::spawn(async move { webhook.dispatch(&action).await });
+                }
+            }
+        });
+    }
+    async fn dispatch(&self, action: &BucketAction) {
+        let body = payload(action, chrono::Utc::now().timestamp_millis());
+        let signature = self.config.secret.as_ref().map(|it| sign(it, &body));
+        for url in &self.config.urls {
+            let mut delay = Duration::from_millis(500);
+            for attempt in 0..=self.config.retries {
+                let mut request = self
+                    .client
+                    .post(url)
+                    .header("content-type", "application/json")
+                    .body(body.clone());
+                if let Some(signature) = &signature {
+                    request = request.header("x-signature", format!("sha256={}", signature));
+                }
+                match request.send().await.and_then(|it| it.error_for_status()) {
+                    Ok(_) => break,
+                    Err(err) if attempt < self.config.retries => {
+                        tracing::debug!(%err, "webhook {} failed, retry in {:?}", url, delay);
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    Err(err) => {
+                        tracing::warn!(%err, "webhook {} failed, {} dropped", url, action)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// JSON body of a delivery, there are no user accounts yet so `user_id` is always null
+fn payload(action: &BucketAction, timestamp: i64) -> String {
+    let (event, uid) = match action {
+        BucketAction::Add(uid) => ("ADD", uid),
+        BucketAction::Delete(uid) => ("DELETE", uid),
+    };
+    serde_json::json!({
+        "event": event,
+        "id": uid,
+        "user_id": null,
+        "timestamp": timestamp,
+    })
+    .to_string()
+}
+
+/// HMAC-SHA256 of the body, hex encoded
+fn sign(secret: &str, body: &str) -> String {
+    use hmac::{Hmac, Mac};
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(body.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_payload() {
+        let uid = Uuid::new_v4();
+        let body: serde_json::Value =
+            serde_json::from_str(&payload(&BucketAction::Delete(uid), 42)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "event": "DELETE", "id": uid, "user_id": null, "timestamp": 42 })
+        );
+    }
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -1,16 +1,22 @@
-use crate::config::state::AppState;
+use crate::config::{state::AppState, CorsConfig};
 use crate::services;
+use anyhow::Context;
 use axum::{
+    http::{HeaderName, HeaderValue, Method},
     routing::{delete, get, head, post},
     Router,
 };
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
 
-pub fn routes() -> Router<AppState> {
+pub fn routes(cors: CorsLayer) -> Router<AppState> {
     let static_files_service = tower_http::services::ServeDir::new(std::path::Path::new("public"))
         .append_index_html_on_directories(true);
     Router::new()
         .route("/api", get(services::list))
         .route("/api/beacon", post(services::beacon))
+        .route("/api/archive", post(services::archive))
+        .route("/api/export", get(services::export))
+        .route("/api/metadata", post(services::get_metadata_batch))
         .route(
             "/api/upload",
             post(services::upload).layer(axum::extract::DefaultBodyLimit::max(4 * 1024 * 1024)),
@@ -20,22 +26,130 @@ pub fn routes() -> Router<AppState> {
             "/api/upload-part/:uuid",
             post(services::upload_part).layer(axum::extract::DefaultBodyLimit::max(1024 * 1024)),
         )
+        .route("/api/upload-ws", get(services::upload_ws))
         .route("/api/upload-preflight", head(services::upload_preflight))
+        .route("/api/upload-probe", post(services::upload_probe))
+        .route(
+            "/api/mimetype",
+            // a prefix of the file is enough for the magic bytes
+            post(services::mimetype).layer(axum::extract::DefaultBodyLimit::max(64 * 1024)),
+        )
         .route("/api/notify", get(services::update_notify))
+        .route("/api/notify/connections", get(services::connections))
+        .route("/api/admin/reconcile", post(services::reconcile))
         .route("/api/:uuid", delete(services::delete))
         .route("/api/:uuid/metadata", get(services::get_metadata))
+        .route("/api/:uuid/verify", get(services::verify))
         .route("/api/:uuid", get(services::get))
         .fallback_service(static_files_service)
         .layer(tower_http::trace::TraceLayer::new_for_http())
-        .layer(
-            tower_http::cors::CorsLayer::new()
-                .allow_origin(tower_http::cors::Any)
-                .allow_methods(tower_http::cors::Any)
-                .allow_headers([
-                    "CONTENT-TYPE".parse().unwrap(),
-                    "ACCESS-TOKEN".parse().unwrap(),
-                    "X-CONTENT-SHA256".parse().unwrap(),
-                    "X-RAW-FILENAME".parse().unwrap(),
-                ]),
-        )
+        .layer(cors)
+}
+
+/// Build the CORS layer, `*` in a list stands for any value
+pub fn cors_layer(config: &CorsConfig) -> anyhow::Result<CorsLayer> {
+    fn is_wildcard(values: &[String]) -> bool {
+        values.iter().any(|it| it == "*")
+    }
+    fn parse<T, E>(values: &[String], field: &str) -> anyhow::Result<Vec<T>>
+    where
+        T: std::str::FromStr<Err = E>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        values
+            .iter()
+            .map(|it| {
+                it.parse::<T>()
+                    .with_context(|| format!("Error: Invalid cors.{} value '{}'", field, it))
+            })
+            .collect()
+    }
+    let origins = if is_wildcard(&config.allow_origins) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(parse::<HeaderValue, _>(
+            &config.allow_origins,
+            "allow_origins",
+        )?)
+    };
+    let methods = if is_wildcard(&config.allow_methods) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(parse::<Method, _>(&config.allow_methods, "allow_methods")?)
+    };
+    let headers = if is_wildcard(&config.allow_headers) {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(parse::<HeaderName, _>(
+            &config.allow_headers,
+            "allow_headers",
+        )?)
+    };
+    let expose_headers = if is_wildcard(&config.expose_headers) {
+        ExposeHeaders::any()
+    } else {
+        ExposeHeaders::list(parse::<HeaderName, _>(
+            &config.expose_headers,
+            "expose_headers",
+        )?)
+    };
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(expose_headers)
+        .allow_credentials(config.allow_credentials);
+    if let Some(max_age) = config.max_age {
+        layer = layer.max_age(std::time::Duration::from_secs(max_age));
+    }
+    Ok(layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cors_layer() {
+        assert!(cors_layer(&CorsConfig::default()).is_ok());
+        let config = CorsConfig {
+            allow_origins: vec!["https://example.com".to_string()],
+            allow_methods: vec!["GET".to_string(), "POST".to_string()],
+            allow_credentials: true,
+            max_age: Some(600),
+            ..CorsConfig::default()
+        };
+        assert!(cors_layer(&config).is_ok());
+        let config = CorsConfig {
+            allow_headers: vec!["not a header".to_string()],
+            ..CorsConfig::default()
+        };
+        assert!(cors_layer(&config).is_err());
+        // the defaults cover the conditional and resumable requests the API takes
+        use axum::{body::Body, http::Request, routing::get};
+        use tower::ServiceExt;
+        let app = Router::new()
+            .route("/", get(|| async {}))
+            .layer(cors_layer(&CorsConfig::default()).unwrap());
+        let preflight = Request::options("/")
+            .header("origin", "https://example.com")
+            .header("access-control-request-method", "GET")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(preflight).await.unwrap();
+        let allowed = response.headers()["access-control-allow-headers"]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("if-match"));
+        assert!(allowed.contains("last-event-id"));
+        let request = Request::get("/")
+            .header("origin", "https://example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(
+            response.headers()["access-control-expose-headers"],
+            "location,etag,content-range"
+        );
+    }
 }
@@ -0,0 +1,204 @@
+use crate::config::state::AppState;
+use crate::errors::{ApiError, InternalError};
+use crate::utils::archive::{self, ZipWriter};
+use crate::utils::{HttpException, HttpResult};
+use crate::{throw_error, utils};
+use axum::{
+    body::{Bytes, StreamBody},
+    debug_handler,
+    extract::State,
+    http::header,
+    response::{AppendHeaders, IntoResponse},
+    Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ArchiveBody {
+    uuids: Vec<Uuid>,
+    format: ArchiveFormat,
+}
+
+/// Stream the selected files as one tar or zip archive.
+///
+/// Files are read chunk by chunk while the archive is written, memory use doesn't depend on
+/// the selection. The length is known upfront, so the response carries a `Content-Length`.
+#[debug_handler]
+pub async fn archive(
+    State(state): State<AppState>,
+    Json(body): Json<ArchiveBody>,
+) -> HttpResult<impl IntoResponse> {
+    use tokio::io::AsyncReadExt;
+    use tokio_stream::{Stream, StreamExt};
+    use tokio_util::io::ReaderStream;
+
+    if body.uuids.is_empty() {
+        throw_error!(
+            HttpException::BadRequest,
+            ApiError::BodyFieldMissing("uuids")
+        )
+    }
+    let bucket = &state.bucket;
+    let mut items = Vec::with_capacity(body.uuids.len());
+    for id in &body.uuids {
+        match bucket.get(id) {
+            Some(item) => items.push(item),
+            None => throw_error!(HttpException::NotFound, ApiError::ResourceNotFound),
+        }
+    }
+    let names = unique_names(items.iter().map(|it| it.get_filename()).collect());
+    let size = items.iter().map(|it| *it.get_size()).sum::<u64>();
+    let limit = state.config.archive.max_size;
+    let files = names
+        .iter()
+        .zip(&items)
+        .map(|(name, item)| (name.as_str(), *item.get_size()))
+        .collect::<Vec<_>>();
+    if size > limit || (body.format == ArchiveFormat::Zip && !ZipWriter::fits(&files)) {
+        throw_error!(
+            HttpException::PayloadTooLarge,
+            ApiError::ArchiveTooLarge { size, limit }
+        )
+    }
+    let length = match body.format {
+        ArchiveFormat::Tar => {
+            files
+                .iter()
+                .map(|(name, size)| {
+                    archive::tar_header(name, *size, 0).len() as u64
+                        + size
+                        + archive::tar_padding(*size)
+                })
+                .sum::<u64>()
+                + archive::TAR_END.len() as u64
+        }
+        ArchiveFormat::Zip => ZipWriter::length(&files),
+    };
+    let entries = names
+        .into_iter()
+        .zip(items)
+        .map(|(name, item)| {
+            (
+                bucket.get_resource_path(&item),
+                name,
+                *item.get_size(),
+                *item.get_created(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let format = body.format;
+    let chunk_size = state.config.streaming.chunk_size;
+    let stream = async_stream::try_stream! {
+        let mut zip = ZipWriter::default();
+        for (path, name, size, mtime) in entries {
+            yield Bytes::from(match format {
+                ArchiveFormat::Tar => archive::tar_header(&name, size, mtime),
+                ArchiveFormat::Zip => zip.begin_file(&name, mtime),
+            });
+            let file = tokio::fs::File::open(&path).await.map_err(|err| {
+                tracing::error!(%err, "{}", InternalError::OpenFile(&path));
+                err
+            })?;
+            // never write more than announced, even if the file has grown
+            let mut reader = ReaderStream::with_capacity(file.take(size), chunk_size);
+            let mut hasher = crc32fast::Hasher::new();
+            let mut written = 0;
+            while let Some(chunk) = reader.next().await {
+                let chunk = chunk?;
+                hasher.update(&chunk);
+                written += chunk.len() as u64;
+                yield chunk;
+            }
+            if written != size {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("{:?} is shorter than its recorded size", path),
+                ))?;
+            }
+            yield Bytes::from(match format {
+                ArchiveFormat::Tar => vec![0; archive::tar_padding(size) as usize],
+                ArchiveFormat::Zip => zip.end_file(hasher.finalize(), size),
+            });
+        }
+        yield Bytes::from(match format {
+            ArchiveFormat::Tar => archive::TAR_END.to_vec(),
+            ArchiveFormat::Zip => zip.finish(),
+        });
+    };
+    // pin down the error type the `?`s above convert into
+    let stream: std::pin::Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>> =
+        Box::pin(stream);
+    let (content_type, ext) = match format {
+        ArchiveFormat::Tar => ("application/x-tar", "tar"),
+        ArchiveFormat::Zip => ("application/zip", "zip"),
+    };
+    let filename = format!(
+        "synclink_{}.{}",
+        chrono::Local::now().format("%Y-%m-%d-%H-%M"),
+        ext
+    );
+    Ok::<_, ()>((
+        AppendHeaders([
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                utils::content_disposition("attachment", &filename),
+            ),
+        ]),
+        StreamBody::new(utils::until_cancelled(
+            utils::throttle(stream, state.config.streaming.rate_limit),
+            state.shutdown.abort.clone(),
+        )),
+    ))
+    .into()
+}
+
+/// Suffix repeated names with ` (n)` so entries don't overwrite each other when extracted
+fn unique_names(names: Vec<String>) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let mut candidate = name.clone();
+            let mut n = 1;
+            while !seen.insert(candidate.clone()) {
+                candidate = utils::numbered_name(&name, n);
+                n += 1;
+            }
+            candidate
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_names() {
+        let names = ["a.txt", "a.txt", "b", "b", "a.txt", ".env", ".env"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            unique_names(names),
+            [
+                "a.txt",
+                "a (1).txt",
+                "b",
+                "b (1)",
+                "a (2).txt",
+                ".env",
+                ".env (1)"
+            ]
+        );
+    }
+}
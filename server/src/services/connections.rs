@@ -0,0 +1,18 @@
+use crate::config::state::AppState;
+use crate::models::connections::ConnectionDto;
+use crate::utils::HttpResult;
+use crate::{try_break_ok, utils};
+use axum::{debug_handler, extract::State, http::HeaderMap, Json};
+
+/// List the connected SSE subscribers, requires the admin token
+#[debug_handler]
+pub async fn connections(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> HttpResult<Json<Vec<ConnectionDto>>> {
+    try_break_ok!(utils::authorize_admin(
+        state.config.admin.as_ref(),
+        &headers
+    ));
+    Ok::<_, ()>(Json(state.connections.snapshot())).into()
+}
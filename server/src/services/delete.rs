@@ -1,26 +1,43 @@
 use crate::config::state::AppState;
+use crate::errors::ApiError;
 use crate::models::bucket::BucketAction;
-use crate::utils::HttpResult;
+use crate::utils::{HttpException, HttpResult};
+use crate::{throw_error, utils};
 use axum::{
     debug_handler,
     extract::{Path, State},
+    http::{header, HeaderMap},
     Json,
 };
 use uuid::Uuid;
 
+/// With `If-Match`, the entry is only deleted while its hash (the ETag of `get`) still matches,
+/// otherwise `412`
 #[debug_handler]
 pub async fn delete(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
+    headers: HeaderMap,
 ) -> HttpResult<Json<String>> {
-    let result = state.bucket.delete(&id).await;
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .map(|it| String::from_utf8_lossy(it.as_bytes()).to_string());
+    let result = state
+        .bucket
+        .delete(&id, |entity| {
+            if_match
+                .as_deref()
+                .is_none_or(|value| utils::is_match(value, entity.map(|it| it.get_hash())))
+        })
+        .await;
     match result {
-        Ok(_) => {
+        Ok(true) => {
             if let Err(err) = state.broadcast.send(BucketAction::Delete(id)) {
                 tracing::warn!("broadcast {} failed", err);
             }
             Ok::<_, ()>(Json("ok!".to_string())).into()
         }
+        Ok(false) => throw_error!(HttpException::PreconditionFailed, ApiError::ETagMismatch),
         Err(err) => Err(err).into(),
     }
 }
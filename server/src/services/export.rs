@@ -0,0 +1,190 @@
+use crate::config::state::AppState;
+use crate::models::bucket::BucketEntity;
+use crate::services::list::BucketEntityDto;
+use crate::utils::HttpResult;
+use axum::{
+    body::{Bytes, StreamBody},
+    debug_handler,
+    extract::{Query, State},
+    http::header,
+    response::{AppendHeaders, IntoResponse},
+};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Records serialized per lock of the index
+const BATCH_SIZE: usize = 256;
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+    /// `<created>:<uid>` of the last record received, an interrupted export resumes after it
+    /// even when that record was deleted meanwhile
+    #[serde(deserialize_with = "deserialize_cursor", default)]
+    after: Option<(i64, Uuid)>,
+}
+
+fn deserialize_cursor<'de, D>(deserializer: D) -> Result<Option<(i64, Uuid)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Deserialize::deserialize(deserializer)?;
+    match s {
+        Some(s) => s
+            .split_once(':')
+            .and_then(|(created, uid)| Some((created.parse().ok()?, uid.parse().ok()?)))
+            .map(Some)
+            .ok_or_else(|| {
+                serde::de::Error::invalid_value(
+                    serde::de::Unexpected::Str(&s),
+                    &"'<created>:<uid>' of a record",
+                )
+            }),
+        None => Ok(None),
+    }
+}
+
+/// Position of the record at `position` when the index was snapshotted, `None` once deleted.
+///
+/// Records are only ever removed from the index or appended to it, so a record can only have
+/// moved towards the front, by as many places as records before it were deleted.
+fn locate(
+    items: &[BucketEntity],
+    positions: &HashMap<Uuid, usize>,
+    uid: &Uuid,
+    position: usize,
+) -> Option<usize> {
+    let mut idx = position.min(items.len().checked_sub(1)?);
+    loop {
+        let current = items[idx].get_uid();
+        if current == uid {
+            return Some(idx);
+        }
+        // reached a record that came before it
+        if positions.get(current).is_some_and(|it| *it < position) {
+            return None;
+        }
+        idx = idx.checked_sub(1)?;
+    }
+}
+
+/// Stream every record as newline-delimited JSON, ordered by creation time then uid.
+///
+/// Only the ids are snapshotted up front, records are serialized batch by batch as the client
+/// reads. Records deleted meanwhile are skipped, records added meanwhile are left out.
+#[debug_handler]
+pub async fn export(
+    State(state): State<AppState>,
+    Query(query): Query<QueryParams>,
+) -> HttpResult<impl IntoResponse> {
+    let mut keys = state.bucket.map_clone(|items| {
+        items
+            .iter()
+            .map(|it| (*it.get_created(), *it.get_uid()))
+            .collect::<Vec<_>>()
+    });
+    let positions: HashMap<Uuid, usize> = keys
+        .iter()
+        .enumerate()
+        .map(|(position, (_, uid))| (*uid, position))
+        .collect();
+    keys.sort_unstable();
+    let start = match query.after {
+        Some(after) => keys.partition_point(|it| *it <= after),
+        None => 0,
+    };
+    let bucket = state.bucket.clone();
+    let stream = async_stream::stream! {
+        for batch in keys[start..].chunks(BATCH_SIZE) {
+            let lines = bucket.map_clone(|items| {
+                let mut lines = Vec::new();
+                for (_, uid) in batch {
+                    let Some(idx) = locate(items, &positions, uid, positions[uid]) else {
+                        continue;
+                    };
+                    serde_json::to_writer(&mut lines, &BucketEntityDto::from(&items[idx]))
+                        .expect("a record serializes");
+                    lines.push(b'\n');
+                }
+                lines
+            });
+            yield Ok::<_, std::convert::Infallible>(Bytes::from(lines));
+        }
+    };
+    Ok::<_, ()>((
+        AppendHeaders([(header::CONTENT_TYPE, "application/x-ndjson")]),
+        StreamBody::new(stream),
+    ))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, HttpBody};
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_resume_after_delete() {
+        let dir = std::env::temp_dir().join(format!("synclink-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = AppState::for_test(&dir).await;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            state.bucket.store_test_file(name, name.as_bytes()).await;
+        }
+        let app =
+            crate::routes::routes(tower_http::cors::CorsLayer::new()).with_state(state.clone());
+        let export = |after: Option<String>| {
+            let uri = match after {
+                Some(after) => format!("/api/export?after={}", after),
+                None => "/api/export".to_string(),
+            };
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let mut body = response.into_body();
+                let mut content = Vec::new();
+                while let Some(chunk) = body.data().await {
+                    content.extend_from_slice(&chunk.unwrap());
+                }
+                String::from_utf8(content)
+                    .unwrap()
+                    .lines()
+                    .map(|it| serde_json::from_str::<serde_json::Value>(it).unwrap())
+                    .map(|it| {
+                        (
+                            it["created"].as_i64().unwrap(),
+                            it["uid"].as_str().unwrap().parse::<Uuid>().unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        let records = export(None).await;
+        let mut sorted = records.clone();
+        sorted.sort_unstable();
+        assert_eq!((records.len(), &records), (3, &sorted));
+        // the client received the first record, then it was deleted
+        let (created, uid) = records[0];
+        assert!(state.bucket.delete(&uid, |_| true).await.unwrap());
+        assert_eq!(
+            export(Some(format!("{}:{}", created, uid))).await,
+            records[1..]
+        );
+        let response = app
+            .oneshot(
+                Request::get(format!("/api/export?after={}", uid))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
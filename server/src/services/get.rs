@@ -1,5 +1,6 @@
 use crate::config::state::AppState;
 use crate::errors::{ApiError, InternalError};
+use crate::models::bucket::{Bucket, BucketEntity};
 use crate::utils::{HttpException, HttpResult};
 use crate::{throw_error, try_break_ok, utils};
 use anyhow::Context;
@@ -12,6 +13,7 @@ use axum::{
     Json,
 };
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::pin::Pin;
 use tokio::io::{AsyncRead, AsyncSeekExt};
 use tokio_stream::Stream;
@@ -19,7 +21,18 @@ use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct GetBucketQueryParams {
+    /// shorthand for `disposition=attachment`
     raw: Option<String>,
+    disposition: Option<Disposition>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    /// display in the browser, with a filename hint for saving
+    Inline,
+    /// force a download
+    Attachment,
 }
 
 #[debug_handler]
@@ -35,6 +48,11 @@ pub async fn get(
     use tokio_util::io::ReaderStream;
 
     let query: GetBucketQueryParams = query.0;
+    let chunk_size = state.config.streaming.chunk_size;
+    let debug_range = state.config.streaming.debug_range;
+    let rate_limit = state.config.streaming.rate_limit;
+    // long downloads end when the shutdown grace period runs out
+    let abort = state.shutdown.abort.clone();
     let (path, item) = {
         let bucket = state.bucket;
         if !bucket.has(&id) {
@@ -42,13 +60,12 @@ pub async fn get(
         }
         bucket
             .get(&id)
-            .map(|it| (bucket.get_storage_path().join(it.get_resource()), it))
+            .map(|it| (bucket.get_resource_path(&it), it))
             .unwrap()
     };
     let ranges = headers
         .get("range")
-        .map(|it| String::from_utf8(it.as_bytes().to_vec()).unwrap())
-        .map(|it| utils::parse_ranges(&it));
+        .map(|it| String::from_utf8_lossy(it.as_bytes()).to_string());
 
     let file = try_break_ok!(tokio::fs::File::open(&path)
         .await
@@ -57,60 +74,86 @@ pub async fn get(
         .metadata()
         .await
         .with_context(|| InternalError::ReadFileMetadata(&path).to_string()));
+    let compression = &state.config.compression;
+    let gzip = ranges.is_none()
+        && compression.enabled
+        && utils::is_compressible(item.get_type())
+        && *item.get_size() >= compression.min_size
+        && utils::accepts_gzip(&headers);
     let mut response_headers = vec![
         (
             header::CONTENT_TYPE,
             format!("{}; charset=utf-8", item.get_type()),
         ),
         (header::ACCEPT_RANGES, "bytes".to_string()),
-        (header::ETAG, item.get_hash().to_string()),
-        (header::CONNECTION, "keep-alive".to_string()),
+        // compressed bytes aren't the stored file, only the identity content is tagged strong
+        (header::ETAG, utils::entity_tag(item.get_hash(), gzip)),
     ];
-    if query.raw.is_some() {
+    let disposition = query
+        .disposition
+        .or(query.raw.as_ref().map(|_| Disposition::Attachment));
+    if let Some(disposition) = disposition {
+        let kind = match disposition {
+            Disposition::Inline => "inline",
+            Disposition::Attachment => "attachment",
+        };
         response_headers.push((
             header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", item.get_filename()),
+            utils::content_disposition(kind, &item.get_filename()),
         ))
     }
     if let Some(last_modified) = utils::last_modified(&metadata) {
         response_headers.push((header::LAST_MODIFIED, last_modified))
     }
+    if utils::is_not_modified(&headers, item.get_hash(), metadata.modified().ok()) {
+        response_headers.retain(|(key, _)| key == header::ETAG || key == header::LAST_MODIFIED);
+        return Ok::<_, ()>(
+            (
+                axum::http::StatusCode::NOT_MODIFIED,
+                axum::response::AppendHeaders(response_headers),
+            )
+                .into_response(),
+        )
+        .into();
+    }
     // 如果指定了 range 则调整文件流的位置
-    // 如果 range 小于 4096，则写入内存，如果 range 大于 4096，则开新的文件句柄进行读取，如果 ranges > 10 则抛出错误 To many range
+    // 如果 range 不超过一个 chunk，则写入内存，否则开新的文件句柄进行读取，如果 ranges > 8 则抛出错误 To many range
     if let Some(ranges) = ranges {
         use tokio::io::SeekFrom;
-        let ranges = try_break_ok!(ranges);
         let total = metadata.len();
+        let requested = ranges;
+        // a range header that is present but can't be served is a 416, not a silent 200
+        let ranges =
+            match utils::evaluate_ranges(&requested, total, state.config.streaming.max_ranges) {
+                Ok(ranges) => ranges,
+                Err(err) => {
+                    let mut response = range_not_satisfiable(total, &err);
+                    if debug_range {
+                        let value = format!("requested={}; rejected={}", requested, err.code());
+                        if let Ok(value) = header::HeaderValue::from_str(&value) {
+                            response.headers_mut().insert(X_DEBUG_RANGE, value);
+                        }
+                    }
+                    return Ok::<_, ()>(response).into();
+                }
+            };
+        // several ranges are answered as multipart/byteranges, each part carrying its own
+        // type and range
+        let boundary = (ranges.len() > 1).then(|| Uuid::new_v4().simple().to_string());
+        let content_type = item.get_type().to_string();
         type PinedStreamPart =
             Pin<Box<dyn Stream<Item = Result<axum::body::Bytes, std::io::Error>> + Send>>;
         let mut streams: Vec<PinedStreamPart> = Vec::new();
         let mut transmitted_length = 0;
-        if ranges.len() > 8 {
-            throw_error!(HttpException::RangeNotSatisfiable, ApiError::RangeTooLarge);
-        }
-        for range in ranges.iter() {
-            let (start, end, is_negative) = match range {
-                (Some(start), Some(end)) => (*start, *end, false),
-                (Some(start), None) => (*start, total - 1, false),
-                (None, Some(last)) => {
-                    let last = (*last).min(total);
-                    (total - last, total, true)
-                }
-                _ => throw_error!(HttpException::RangeNotSatisfiable, ApiError::InvalidRange),
-            };
-            // 如果指定了 range-end 则取部分值
-            let end = end.min(total);
-            let len = if is_negative {
-                end - start
-            } else {
-                end - start + 1
-            };
+        for &(start, end) in ranges.iter() {
+            if let Some(boundary) = &boundary {
+                let part = utils::byteranges_part(boundary, &content_type, (start, end), total);
+                transmitted_length += part.len() as u64;
+                streams.push(Box::pin(tokio_stream::once(Ok(part.into()))));
+            }
+            let len = end - start + 1;
             transmitted_length += len;
-            // println!(
-            //     "range: start={}, end={}, is_negative={}, len={}, total={}",
-            //     start, end, is_negative, len, total
-            // );
-            if len > 4096 {
+            if len > chunk_size as u64 {
                 let mut file = try_break_ok!(tokio::fs::File::open(&path)
                     .await
                     .with_context(|| InternalError::OpenFile(&path).to_string()));
@@ -118,7 +161,7 @@ pub async fn get(
                     .seek(SeekFrom::Start(start))
                     .await
                     .with_context(|| InternalError::SeekFile));
-                let stream = ReaderStream::new(file.take(len));
+                let stream = ReaderStream::with_capacity(file.take(len), chunk_size);
                 streams.push(Box::pin(stream));
             } else {
                 let mut file = try_break_ok!(file
@@ -140,23 +183,48 @@ pub async fn get(
                 streams.push(Box::pin(stream));
             }
         }
+        if let Some(boundary) = &boundary {
+            let end = utils::byteranges_end(boundary);
+            transmitted_length += end.len() as u64;
+            streams.push(Box::pin(tokio_stream::once(Ok(end.into()))));
+        }
 
         let combine_stream = streams.into_iter().fold(None, |acc, stream| match acc {
             None => Some(stream),
             Some(combine_stream) => Some(Box::pin(combine_stream.chain(stream))),
         });
         let combine_stream = match combine_stream
-            .map(StreamBody::new)
+            .map(|it| {
+                StreamBody::new(utils::until_cancelled(
+                    utils::throttle(it, rate_limit),
+                    abort.clone(),
+                ))
+            })
             .with_context(|| ApiError::RangeNotFound)
         {
             Ok(stream) => stream,
             Err(err) => throw_error!(HttpException::RangeNotSatisfiable, err),
         };
         response_headers.push((header::CONTENT_LENGTH, transmitted_length.to_string()));
-        response_headers.push((
-            header::CONTENT_RANGE,
-            format!("bytes {}", utils::format_ranges(&ranges, total)),
-        ));
+        if debug_range {
+            response_headers.push((
+                X_DEBUG_RANGE,
+                describe_ranges(&requested, &ranges, transmitted_length),
+            ));
+        }
+        match &boundary {
+            Some(boundary) => {
+                for (key, value) in response_headers.iter_mut() {
+                    if *key == header::CONTENT_TYPE {
+                        *value = format!("multipart/byteranges; boundary={}", boundary);
+                    }
+                }
+            }
+            None => response_headers.push((
+                header::CONTENT_RANGE,
+                format!("bytes {}", utils::format_ranges(&ranges, total)),
+            )),
+        }
         Ok::<_, ()>(
             (
                 axum::http::StatusCode::PARTIAL_CONTENT,
@@ -167,12 +235,66 @@ pub async fn get(
         )
         .into()
     } else {
+        // `Vary: accept-encoding` (set along with the CORS headers) tells caches to key on the
+        // encoding
+        if gzip {
+            use async_compression::tokio::bufread::GzipEncoder;
+            response_headers.push((header::CONTENT_ENCODING, "gzip".to_string()));
+            if debug_range {
+                response_headers.push((X_DEBUG_RANGE, "requested=none; length=gzip".to_string()));
+            }
+            let encoder = GzipEncoder::new(tokio::io::BufReader::with_capacity(chunk_size, file));
+            let stream = ReaderStream::with_capacity(encoder, chunk_size);
+            let stream = utils::throttle(stream, rate_limit);
+            let body = StreamBody::new(utils::until_cancelled(stream, abort)).into_response();
+            return Ok::<_, ()>(
+                (axum::response::AppendHeaders(response_headers), body).into_response(),
+            )
+            .into();
+        }
         response_headers.push((header::CONTENT_LENGTH, item.get_size().to_string()));
-        let body = StreamBody::new(ReaderStream::new(file)).into_response();
+        if debug_range {
+            response_headers.push((
+                X_DEBUG_RANGE,
+                format!("requested=none; length={}", item.get_size()),
+            ));
+        }
+        let stream = utils::throttle(ReaderStream::with_capacity(file, chunk_size), rate_limit);
+        let body = StreamBody::new(utils::until_cancelled(stream, abort)).into_response();
         Ok::<_, ()>((axum::response::AppendHeaders(response_headers), body).into_response()).into()
     }
 }
 
+const X_DEBUG_RANGE: axum::http::HeaderName = axum::http::HeaderName::from_static("x-debug-range");
+
+/// `X-Debug-Range` value of a partial response, ranges listed in the header are merged when
+/// they overlap or touch, so fewer may be served
+fn describe_ranges(requested: &str, served: &[(u64, u64)], length: u64) -> String {
+    let merged = requested.split(',').count() != served.len();
+    let served = served
+        .iter()
+        .map(|(start, end)| format!("{}-{}", start, end))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "requested={}; served={}; merged={}; length={}",
+        requested, served, merged, length
+    )
+}
+
+/// 416 response, `Content-Range` carries the current length so the client can retry
+fn range_not_satisfiable(total: u64, reason: &ApiError) -> axum::response::Response {
+    (
+        axum::http::StatusCode::RANGE_NOT_SATISFIABLE,
+        axum::response::AppendHeaders([(
+            axum::http::header::CONTENT_RANGE,
+            format!("bytes */{}", total),
+        )]),
+        utils::error_body(reason.code(), &reason.to_string(), reason.details()),
+    )
+        .into_response()
+}
+
 #[debug_handler]
 pub async fn get_metadata(
     State(state): State<AppState>,
@@ -185,3 +307,83 @@ pub async fn get_metadata(
         throw_error!(HttpException::NotFound, ApiError::ResourceNotFound)
     }
 }
+
+#[derive(Deserialize, Debug)]
+pub struct MetadataBatchBody {
+    uuids: Vec<Uuid>,
+}
+
+/// Metadata of several files at once, keyed by id. Ids of files that don't exist are left
+/// out, duplicates are answered once.
+#[debug_handler]
+pub async fn get_metadata_batch(
+    State(state): State<AppState>,
+    Json(body): Json<MetadataBatchBody>,
+) -> HttpResult<impl IntoResponse> {
+    let limit = state.config.metadata.max_batch_size;
+    match metadata_batch(&state.bucket, body.uuids, limit) {
+        Ok(items) => Ok::<_, ()>(Json(items)).into(),
+        Err(err) => throw_error!(HttpException::BadRequest, err),
+    }
+}
+
+/// Entries of the distinct `uuids` that exist, fails when there are more than `limit` of them
+fn metadata_batch(
+    bucket: &Bucket,
+    mut uuids: Vec<Uuid>,
+    limit: usize,
+) -> Result<BTreeMap<Uuid, BucketEntity>, ApiError<'static>> {
+    uuids.sort_unstable();
+    uuids.dedup();
+    if uuids.len() > limit {
+        return Err(ApiError::BatchTooLarge { limit });
+    }
+    Ok(uuids
+        .into_iter()
+        .filter_map(|id| bucket.get(&id).map(|item| (id, item)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_ranges() {
+        assert_eq!(
+            describe_ranges("bytes=0-1,4-5", &[(0, 1), (4, 5)], 4),
+            "requested=bytes=0-1,4-5; served=0-1,4-5; merged=false; length=4"
+        );
+        assert_eq!(
+            describe_ranges("bytes=0-9, 5-19", &[(0, 19)], 20),
+            "requested=bytes=0-9, 5-19; served=0-19; merged=true; length=20"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metadata_batch() {
+        let dir = std::env::temp_dir().join(format!("synclink-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bucket = Bucket::connect(&dir, Vec::new()).await;
+        let stored = [
+            bucket.store_test_file("a.txt", b"a").await,
+            bucket.store_test_file("b.txt", b"b").await,
+        ];
+        let missing = Uuid::new_v4();
+        let ids = vec![stored[0], missing, stored[0], stored[1], stored[1]];
+        // three distinct ids fit a limit of 3 despite five being sent
+        let items = metadata_batch(&bucket, ids.clone(), 3).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[&stored[0]].get_name(), "a.txt");
+        assert_eq!(items[&stored[1]].get_name(), "b.txt");
+        assert!(!items.contains_key(&missing));
+        assert!(matches!(
+            metadata_batch(&bucket, ids, 2),
+            Err(ApiError::BatchTooLarge { limit: 2 })
+        ));
+        assert!(metadata_batch(&bucket, vec![missing], 1)
+            .unwrap()
+            .is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
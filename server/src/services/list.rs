@@ -1,4 +1,5 @@
 use crate::config::state::AppState;
+use crate::models::bucket::BucketEntity;
 use crate::utils::HttpResult;
 use axum::{
     debug_handler,
@@ -16,6 +17,44 @@ pub struct QueryParams {
     page: Option<u32>,
     per_page: Option<u32>,
     fields: Option<String>,
+    /// case-insensitive keyword matched against the file name
+    q: Option<String>,
+    #[serde(default)]
+    sort: SortOrder,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// newest first
+    #[default]
+    CreatedDesc,
+    CreatedAsc,
+    /// case-insensitive
+    NameAsc,
+    NameDesc,
+    /// largest first
+    SizeDesc,
+}
+
+impl SortOrder {
+    /// Ties are broken by the newest first, so pages stay stable
+    fn compare(&self, a: &BucketEntity, b: &BucketEntity) -> std::cmp::Ordering {
+        let newest_first = b.get_created().cmp(a.get_created());
+        match self {
+            SortOrder::CreatedDesc => newest_first,
+            SortOrder::CreatedAsc => a.get_created().cmp(b.get_created()),
+            SortOrder::NameAsc => compare_names(a, b).then(newest_first),
+            SortOrder::NameDesc => compare_names(b, a).then(newest_first),
+            SortOrder::SizeDesc => b.get_size().cmp(a.get_size()).then(newest_first),
+        }
+    }
+}
+
+fn compare_names(a: &BucketEntity, b: &BucketEntity) -> std::cmp::Ordering {
+    a.get_name()
+        .to_lowercase()
+        .cmp(&b.get_name().to_lowercase())
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,6 +68,20 @@ pub struct BucketEntityDto {
     user_agent: Option<String>,
 }
 
+impl From<&BucketEntity> for BucketEntityDto {
+    fn from(it: &BucketEntity) -> Self {
+        Self {
+            uid: *it.get_uid(),
+            created: *it.get_created(),
+            name: it.get_name().to_string(),
+            size: *it.get_size(),
+            r#type: it.get_type().to_string(),
+            ext: it.get_extension().to_owned(),
+            user_agent: it.get_user_agent().to_owned(),
+        }
+    }
+}
+
 impl BucketEntityDto {
     fn into_value(self) -> serde_json::Value {
         serde_json::json!(self)
@@ -70,10 +123,20 @@ pub struct PaginationDto<T>
 where
     T: Serialize,
 {
+    /// number of entries in the bucket, filters not applied
     total: usize,
+    page: usize,
+    per_page: usize,
+    /// whether entries matching the filters follow this page
+    has_more: bool,
     data: Vec<T>,
 }
 
+/// `keyword` is expected to be lowercased already
+fn matches_keyword(name: &str, keyword: &str) -> bool {
+    name.to_lowercase().contains(keyword)
+}
+
 #[debug_handler]
 pub async fn list(
     State(state): State<AppState>,
@@ -90,12 +153,18 @@ pub async fn list(
                 .collect::<HashSet<_>>()
         })
         .unwrap_or_default();
+    let keyword = query
+        .q
+        .as_deref()
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .map(str::to_lowercase);
     let mut total = 0usize;
-    let items = state.bucket.map_clone(|items| {
+    let mut items = state.bucket.map_clone(|items| {
         total = items.len();
         let sorted_indexes = {
             let mut indexes = (0..total).collect::<Vec<_>>();
-            indexes.sort_unstable_by(|&a, &b| items[b].get_created().cmp(items[a].get_created()));
+            indexes.sort_by(|&a, &b| query.sort.compare(&items[a], &items[b]));
             indexes
         };
         sorted_indexes
@@ -103,26 +172,21 @@ pub async fn list(
             .filter(|&idx| {
                 let it = &items[idx];
                 let created = *it.get_created();
-                (query.before.map_or(true, |before| created < before))
-                    && (query.after.map_or(true, |after| created > after))
+                (query.before.is_none_or(|before| created < before))
+                    && (query.after.is_none_or(|after| created > after))
+                    && (keyword
+                        .as_ref()
+                        .is_none_or(|keyword| matches_keyword(it.get_name(), keyword)))
             })
             .skip(page * per_page - per_page)
-            .take(per_page)
-            .map(|idx| {
-                let it = &items[idx];
-                BucketEntityDto {
-                    uid: *it.get_uid(),
-                    created: *it.get_created(),
-                    name: it.get_name().to_string(),
-                    size: *it.get_size(),
-                    r#type: it.get_type().to_string(),
-                    ext: it.get_extension().to_owned(),
-                    user_agent: it.get_user_agent().to_owned(),
-                }
-            })
+            // one more than requested tells whether another page follows
+            .take(per_page + 1)
+            .map(|idx| BucketEntityDto::from(&items[idx]))
             .collect::<Vec<_>>()
     });
 
+    let has_more = items.len() > per_page;
+    items.truncate(per_page);
     let data = if fields.is_empty() {
         items
             .into_iter()
@@ -138,5 +202,54 @@ pub async fn list(
             })
             .collect::<Vec<_>>()
     };
-    Ok::<_, ()>(Json(PaginationDto { total, data })).into()
+    Ok::<_, ()>(Json(PaginationDto {
+        total,
+        page,
+        per_page,
+        has_more,
+        data,
+    }))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_keyword() {
+        assert!(matches_keyword("Report-2023.PDF", "report"));
+        assert!(matches_keyword("Report-2023.PDF", ".pdf"));
+        assert!(matches_keyword("会议记录.txt", "会议"));
+        assert!(!matches_keyword("pasted_2023-06-01-09-22", "report"));
+    }
+
+    #[test]
+    fn test_sort_order() {
+        let entity = |name: &str, created: &str, size: u64| -> BucketEntity {
+            toml::from_str(&format!(
+                "uid = \"{}\"\ncreated = \"{}\"\nname = \"{}\"\nhash = \"\"\nsize = {}\ntype = \"text/plain\"",
+                Uuid::new_v4(),
+                created,
+                name,
+                size
+            ))
+            .unwrap()
+        };
+        let items = [
+            entity("b.txt", "2023-06-01 09:00:00 UTC", 10),
+            entity("A.txt", "2023-06-02 09:00:00 UTC", 30),
+            entity("c.txt", "2023-06-03 09:00:00 UTC", 20),
+        ];
+        let sorted = |order: SortOrder| {
+            let mut items = items.iter().collect::<Vec<_>>();
+            items.sort_by(|a, b| order.compare(a, b));
+            items.iter().map(|it| it.get_name()).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(SortOrder::CreatedDesc), ["c.txt", "A.txt", "b.txt"]);
+        assert_eq!(sorted(SortOrder::CreatedAsc), ["b.txt", "A.txt", "c.txt"]);
+        assert_eq!(sorted(SortOrder::NameAsc), ["A.txt", "b.txt", "c.txt"]);
+        assert_eq!(sorted(SortOrder::NameDesc), ["c.txt", "b.txt", "A.txt"]);
+        assert_eq!(sorted(SortOrder::SizeDesc), ["A.txt", "c.txt", "b.txt"]);
+    }
 }
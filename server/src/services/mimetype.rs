@@ -0,0 +1,22 @@
+use crate::utils;
+use axum::{body::Bytes, debug_handler, extract::Query, Json};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+pub struct MimetypeQueryParams {
+    filename: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MimetypeDto {
+    mimetype: String,
+}
+
+/// Guess the mime type from a prefix of the file, so a client can check what the server
+/// would detect before uploading
+#[debug_handler]
+pub async fn mimetype(Query(query): Query<MimetypeQueryParams>, body: Bytes) -> Json<MimetypeDto> {
+    Json(MimetypeDto {
+        mimetype: utils::guess_mimetype(&body, query.filename.as_deref()),
+    })
+}
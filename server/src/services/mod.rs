@@ -1,17 +1,33 @@
+mod archive;
 mod beacon;
+mod connections;
 mod delete;
+mod export;
 mod get;
 mod list;
+mod mimetype;
+mod reconcile;
 mod update_notify;
 mod upload;
 mod upload_part;
 mod upload_preflight;
+mod upload_probe;
+mod upload_ws;
+mod verify;
 
+pub use archive::archive;
 pub use beacon::beacon;
+pub use connections::connections;
 pub use delete::delete;
-pub use get::{get, get_metadata};
+pub use export::export;
+pub use get::{get, get_metadata, get_metadata_batch};
 pub use list::list;
+pub use mimetype::mimetype;
+pub use reconcile::reconcile;
 pub use update_notify::update_notify;
 pub use upload::upload;
 pub use upload_part::upload_part;
 pub use upload_preflight::upload_preflight;
+pub use upload_probe::upload_probe;
+pub use upload_ws::upload_ws;
+pub use verify::verify;
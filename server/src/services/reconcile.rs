@@ -0,0 +1,58 @@
+use crate::config::state::AppState;
+use crate::errors::InternalError;
+use crate::models::bucket::{BucketAction, ReconcileReport};
+use crate::utils::HttpResult;
+use crate::{try_break_ok, utils};
+use axum::{
+    debug_handler,
+    extract::{Query, State},
+    http::HeaderMap,
+    Json,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct ReconcileQueryParams {
+    /// only report, default true
+    dry_run: Option<bool>,
+}
+
+/// Compare the index with the storage directory and optionally repair it, requires the
+/// admin token
+#[debug_handler]
+pub async fn reconcile(
+    State(state): State<AppState>,
+    Query(query): Query<ReconcileQueryParams>,
+    headers: HeaderMap,
+) -> HttpResult<Json<ReconcileReport>> {
+    try_break_ok!(utils::authorize_admin(
+        state.config.admin.as_ref(),
+        &headers
+    ));
+    let fix = !query.dry_run.unwrap_or(true);
+    let bucket = state.bucket.clone();
+    let report = match tokio::task::spawn_blocking(move || bucket.reconcile(fix))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|it| it)
+    {
+        Ok(report) => report,
+        Err(err) => return Err(err).into(),
+    };
+    tracing::info!(
+        fix,
+        "Reconciled, {} orphaned files, {} dangling entries, {} inconsistent entries, {} corrupt entries",
+        report.orphaned_files.len(),
+        report.dangling_entries.len(),
+        report.inconsistent_entries.len(),
+        report.corrupt_entries.len()
+    );
+    if fix {
+        for uid in &report.dangling_entries {
+            if let Err(err) = state.broadcast.send(BucketAction::Delete(*uid)) {
+                tracing::warn!(%err, "{}", InternalError::Broadcast(&format!("delete {} action", uid)));
+            }
+        }
+    }
+    Ok::<_, ()>(Json(report)).into()
+}
@@ -1,21 +1,46 @@
 use crate::config::state::AppState;
 use axum::{
     debug_handler,
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::HeaderMap,
     response::{sse, Sse},
 };
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct NotifyQueryParams {
+    /// start with a `SNAPSHOT` event listing the ids currently stored
+    snapshot: Option<bool>,
+}
 
 #[debug_handler]
 pub async fn update_notify(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Query(query): Query<NotifyQueryParams>,
     headers: HeaderMap,
 ) -> Sse<impl tokio_stream::Stream<Item = Result<sse::Event, std::convert::Infallible>>> {
     let user_agent = headers
         .get("user-agent")
         .map(|it| String::from_utf8(it.as_bytes().to_vec()).unwrap())
         .unwrap_or("Unknown user_agent".into());
-    tracing::info!("`{}` connected", user_agent);
+    // EventSource sends the id of the last received event when it reconnects
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.trim().parse::<u64>().ok());
+    let forwarded_for = headers
+        .get("x-forwarded-for")
+        .and_then(|it| it.to_str().ok())
+        .map(|it| it.to_string());
+    let connection = state
+        .connections
+        .register(addr, forwarded_for, user_agent.clone());
+    tracing::info!(
+        "`{}` connected, connection #{}",
+        user_agent,
+        connection.id()
+    );
     struct Guard {
         user_agent: String,
     }
@@ -26,20 +51,58 @@ pub async fn update_notify(
     }
     use async_stream::try_stream;
     use axum::response::sse;
-    let mut receiver = state.broadcast.subscribe();
+    // a snapshot is a complete starting point, it replaces the replay
+    let (snapshot, replay, mut subscription) = if query.snapshot.unwrap_or(false) {
+        let bucket = state.bucket.clone();
+        let (last_id, uids, subscription) = state.broadcast.subscribe_with_snapshot(|| {
+            bucket.map_clone(|items| items.iter().map(|it| *it.get_uid()).collect())
+        });
+        (Some((last_id, uids)), Vec::new(), subscription)
+    } else {
+        let (replay, subscription) = state.broadcast.subscribe(last_event_id);
+        (None, replay, subscription)
+    };
+    let notifier = state.broadcast.clone();
+    let shutdown = state.shutdown.signal.clone();
+    let max_idle = state
+        .config
+        .notify
+        .max_idle
+        .map(std::time::Duration::from_secs);
+    let keep_alive = std::time::Duration::from_secs(state.config.notify.keep_alive);
     let stream = try_stream! {
         let _guard = Guard{ user_agent };
+        if let Some((last_id, uids)) = snapshot {
+            connection.delivered();
+            let data = serde_json::json!({ "type": "SNAPSHOT", "uids": uids }).to_string();
+            yield sse::Event::default().id(last_id.to_string()).data(data);
+        }
+        for (id, action) in replay {
+            connection.delivered();
+            yield sse::Event::default().id(id.to_string()).data(action.to_json());
+        }
         loop{
-            match receiver.recv().await{
-                Ok(i) => {
-                    let event = sse::Event::default().data(i.to_json());
-                    yield event;
-                },
-                Err(err) => {
-                    tracing::error!(error = ?err, "Failed to get");
+            // keep-alive comments can be buffered by the kernel for a long time before a write
+            // to a vanished client fails, closing idle streams bounds how long they linger
+            let idle = async {
+                match max_idle {
+                    Some(max_idle) => tokio::time::sleep(max_idle).await,
+                    None => std::future::pending().await,
                 }
+            };
+            let received = tokio::select! {
+                // end the stream so the graceful shutdown isn't held open by idle subscribers
+                _ = shutdown.cancelled() => break,
+                _ = idle => break,
+                received = notifier.recv(&mut subscription) => received,
+            };
+            let Some(events) = received else { break };
+            for (id, action) in events {
+                connection.delivered();
+                let event = sse::Event::default().id(id.to_string()).data(action.to_json());
+                yield event;
             }
         }
     };
-    Sse::new(stream).keep_alive(sse::KeepAlive::default())
+    Sse::new(stream).keep_alive(sse::KeepAlive::new().interval(keep_alive))
 }
@@ -11,17 +11,93 @@ use axum::{
     Json,
 };
 
+use crate::config::Durability;
 use crate::errors::{ApiError, InternalError};
+use crate::models::bucket::PreallocationFile;
+use crate::models::upload_session::{InterruptedUpload, UploadSessions, RESUME_TTL};
+use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio_stream::StreamExt;
 
+/// Keeps a partially received upload for [`RESUME_TTL`] when the request ends before the body
+/// does, be it through a read error or the connection going away with the handler
+pub(super) struct ResumeGuard {
+    sessions: Arc<UploadSessions>,
+    hash: String,
+    upload: Option<InterruptedUpload>,
+}
+
+impl ResumeGuard {
+    pub(super) fn new(
+        sessions: Arc<UploadSessions>,
+        hash: String,
+        upload: InterruptedUpload,
+    ) -> Self {
+        Self {
+            sessions,
+            hash,
+            upload: Some(upload),
+        }
+    }
+    pub(super) fn upload(&mut self) -> &mut InterruptedUpload {
+        self.upload
+            .as_mut()
+            .expect("upload taken from a disarmed guard")
+    }
+    /// The upload is complete or failed for good, nothing to keep
+    pub(super) fn disarm(&mut self) -> InterruptedUpload {
+        self.upload.take().expect("guard disarmed twice")
+    }
+}
+
+impl Drop for ResumeGuard {
+    fn drop(&mut self) {
+        let Some(upload) = self.upload.take() else {
+            return;
+        };
+        tracing::debug!(
+            "upload of {} interrupted at {}/{} bytes",
+            self.hash,
+            upload.written,
+            upload.total
+        );
+        let path = upload.path.clone();
+        if let Some(replaced) = self.sessions.interrupt(self.hash.clone(), upload) {
+            if replaced.path != path {
+                let _ = std::fs::remove_file(&replaced.path);
+            }
+        }
+        let sessions = self.sessions.clone();
+        let hash = self.hash.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(RESUME_TTL).await;
+            if let Some(expired) = sessions.expire_interrupted(&hash) {
+                let _ = tokio::fs::remove_file(&expired.path).await;
+            }
+        });
+    }
+}
+
+/// Open the partial file of an interrupted upload positioned at the end of what was written
+pub(super) async fn reopen(upload: &InterruptedUpload) -> anyhow::Result<tokio::fs::File> {
+    use tokio::io::AsyncSeekExt;
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&upload.path)
+        .await
+        .with_context(|| InternalError::OpenFile(&upload.path).to_string())?;
+    file.seek(std::io::SeekFrom::Start(upload.written))
+        .await
+        .with_context(|| InternalError::SeekFile)?;
+    Ok(file)
+}
+
 #[debug_handler]
 pub async fn upload(
     State(state): State<AppState>,
     headers: HeaderMap,
     mut stream: BodyStream,
 ) -> HttpResult<impl IntoResponse> {
-    use sha2::{Digest, Sha256};
     use std::str::FromStr;
 
     let content_length = try_break_ok!(headers
@@ -47,6 +123,15 @@ pub async fn upload(
             HttpException::BadRequest,
             ApiError::HeaderFieldMissing("X-Content-Sha256")
         )));
+    let algorithm = state.config.file_storage.hash;
+    if utils::HashAlgorithm::of(&content_hash) != Some(algorithm) {
+        throw_error!(
+            HttpException::BadRequest,
+            ApiError::UnexpectedHashAlgorithm {
+                expected: algorithm.name()
+            }
+        )
+    }
     let filename = headers
         .get("x-raw-filename")
         .and_then(|it| it.to_str().ok())
@@ -56,6 +141,16 @@ pub async fn upload(
         .get("user-agent")
         .and_then(|it| it.to_str().ok())
         .map(|it| it.to_string());
+    if state
+        .config
+        .blocklist
+        .blocks(filename.as_deref(), Some(&content_type))
+    {
+        throw_error!(
+            HttpException::UnsupportedMediaType,
+            ApiError::ForbiddenContentType
+        )
+    }
 
     // Check hash exists, if it exists, then cancel upload and return uuid
     if let Some(uuid) = state.bucket.has_hash(&content_hash) {
@@ -68,27 +163,121 @@ pub async fn upload(
         )
         .into();
     }
-    let (uid, size, hash) = {
-        // Preallocate disk space, uuid
-        let mut preallocation = match state
-            .bucket
-            .preallocation(&filename, &Some(content_length))
-            .await
+    // the body is what is left to write, a resumed upload is already partly on disk
+    if !state
+        .bucket
+        .has_room(content_length, state.config.upload.reserved_space)
+    {
+        throw_error!(
+            HttpException::InsufficientStorage,
+            ApiError::DiskQuotaExceeded
+        )
+    }
+    // a retry carrying `Content-Range` continues an interrupted upload of the same content
+    let resume = match headers.get("content-range") {
+        Some(value) => match value.to_str().ok().and_then(utils::parse_content_range) {
+            Some(range) => Some(range),
+            None => throw_error!(HttpException::BadRequest, ApiError::InvalidRange),
+        },
+        None => None,
+    };
+    let interrupted = state.upload_sessions.take_interrupted(&content_hash);
+    let interrupted = match (resume, interrupted) {
+        // the file extension comes from the filename, a retry under another extension would
+        // be indexed at a path the partial file isn't at
+        (Some(_), Some(upload))
+            if upload.path != state.bucket.upload_path(&upload.uid, &filename) =>
         {
-            Ok(tup) => tup,
-            Err(err) => return Err(err).into(),
+            state
+                .upload_sessions
+                .restore_interrupted(content_hash.clone(), upload);
+            throw_error!(
+                HttpException::RangeNotSatisfiable,
+                ApiError::ResumeUnavailable { offset: None }
+            )
+        }
+        (Some((start, end, total)), Some(upload))
+            if upload.written == start
+                && upload.total == total
+                && end + 1 == total
+                && total - start == content_length =>
+        {
+            Some(upload)
+        }
+        (Some(_), upload) => {
+            let offset = upload.as_ref().map(|it| it.written);
+            if let Some(upload) = upload {
+                state
+                    .upload_sessions
+                    .restore_interrupted(content_hash.clone(), upload);
+            }
+            throw_error!(
+                HttpException::RangeNotSatisfiable,
+                ApiError::ResumeUnavailable { offset }
+            )
+        }
+        // starting over, the partial file of the earlier attempt is of no use
+        (None, Some(upload)) => {
+            let _ = tokio::fs::remove_file(&upload.path).await;
+            None
+        }
+        (None, None) => None,
+    };
+    let durability = state.config.file_storage.durability;
+    let (uid, size, hash) = {
+        let (mut preallocation, upload) = match interrupted {
+            Some(upload) => {
+                let file = match reopen(&upload).await {
+                    Ok(file) => file,
+                    Err(err) => {
+                        // still resumable once whatever kept the file from opening is gone
+                        state
+                            .upload_sessions
+                            .restore_interrupted(content_hash.clone(), upload);
+                        return Err(err).into();
+                    }
+                };
+                let preallocation = PreallocationFile {
+                    uid: upload.uid,
+                    file,
+                    path: upload.path.clone(),
+                };
+                (preallocation, upload)
+            }
+            None => {
+                // Preallocate disk space, uuid
+                let preallocation = match state
+                    .bucket
+                    .preallocation(&filename, &Some(content_length))
+                    .await
+                {
+                    Ok(tup) => tup,
+                    Err(err) => return Err(err).into(),
+                };
+                let upload = InterruptedUpload::new(
+                    preallocation.uid,
+                    preallocation.path.clone(),
+                    algorithm.hasher(),
+                    content_length,
+                );
+                (preallocation, upload)
+            }
         };
-        let mut hasher = Sha256::new();
-        let mut size = 0;
-        while let Some(chunk) = stream.next().await {
-            let chunk = match chunk.with_context(|| InternalError::ReadStream) {
-                Ok(v) => v,
-                Err(err) => {
+        let mut guard =
+            ResumeGuard::new(state.upload_sessions.clone(), content_hash.clone(), upload);
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = state.shutdown.abort.cancelled() => {
+                    guard.disarm();
                     cleanup_preallocation!(preallocation);
-                    return Err(err).into();
+                    throw_error!(HttpException::ServiceUnavailable, ApiError::ServerShuttingDown)
                 }
             };
-            hasher.update(chunk.as_ref());
+            let Some(chunk) = chunk else { break };
+            // the guard keeps what was received so far for a retry
+            let chunk = try_break_ok!(chunk.with_context(|| InternalError::ReadStream));
+            guard.upload().hasher.update(chunk.as_ref());
             match preallocation
                 .file
                 .write_all(chunk.as_ref())
@@ -97,18 +286,47 @@ pub async fn upload(
             {
                 Ok(_) => (),
                 Err(err) => {
+                    guard.disarm();
                     cleanup_preallocation!(preallocation);
                     return Err(err).into();
                 }
             }
-            size += chunk.len()
+            if durability >= Durability::PerChunk {
+                try_break_ok!(preallocation
+                    .file
+                    .sync_data()
+                    .await
+                    .with_context(|| InternalError::WriteFile(&preallocation.path).to_string()));
+            }
+            guard.upload().written += chunk.len() as u64;
         }
-        let hash = format!("{:x}", hasher.finalize());
+        let upload = guard.disarm();
+        let hash = upload.hasher.finalize();
         if hash.as_str() != content_hash {
             cleanup_preallocation!(preallocation);
             throw_error!(HttpException::BadRequest, ApiError::HashMismatch)
         }
-        (preallocation.uid, size, hash)
+        // the declared type was checked up front, what the content turns out to be only now
+        if !state.config.blocklist.mimetypes.is_empty() {
+            let sniffed = try_break_ok!(utils::sniff_mimetype(&preallocation.path)
+                .await
+                .with_context(|| InternalError::OpenFile(&preallocation.path).to_string()));
+            if sniffed.is_some_and(|it| state.config.blocklist.blocks_mimetype(&it)) {
+                cleanup_preallocation!(preallocation);
+                throw_error!(
+                    HttpException::UnsupportedMediaType,
+                    ApiError::ForbiddenContentType
+                )
+            }
+        }
+        if durability >= Durability::OnFinalize {
+            try_break_ok!(preallocation
+                .file
+                .sync_data()
+                .await
+                .with_context(|| InternalError::WriteFile(&preallocation.path).to_string()));
+        }
+        (preallocation.uid, upload.written as usize, hash)
     };
     try_break_ok!(
         state
@@ -121,3 +339,103 @@ pub async fn upload(
     }
     Ok::<_, ()>((StatusCode::CREATED, Json(uid)).into_response()).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, Bytes};
+    use axum::http::Request;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    /// An upload of `length` bytes whose connection drops after `body` when it is shorter
+    fn request(
+        filename: &str,
+        hash: &str,
+        length: u64,
+        range: Option<&str>,
+        body: &'static [u8],
+    ) -> Request<Body> {
+        let mut request = Request::post("/api/upload")
+            .header("content-type", "text/plain")
+            .header("content-length", length)
+            .header("x-content-sha256", hash)
+            .header("x-raw-filename", filename);
+        if let Some(range) = range {
+            request = request.header("content-range", range);
+        }
+        let mut chunks: Vec<std::io::Result<Bytes>> = vec![Ok(Bytes::from_static(body))];
+        if (body.len() as u64) < length {
+            chunks.push(Err(std::io::ErrorKind::ConnectionReset.into()));
+        }
+        request
+            .body(Body::wrap_stream(tokio_stream::iter(chunks)))
+            .unwrap()
+    }
+
+    fn sha256(content: &[u8]) -> String {
+        let mut hasher = utils::HashAlgorithm::Sha256.hasher();
+        hasher.update(content);
+        hasher.finalize()
+    }
+
+    #[tokio::test]
+    async fn test_empty_upload() {
+        let dir = std::env::temp_dir().join(format!("synclink-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = AppState::for_test(&dir).await;
+        let app =
+            crate::routes::routes(tower_http::cors::CorsLayer::new()).with_state(state.clone());
+        let hash = sha256(b"");
+        let response = app
+            .oneshot(request("empty.txt", &hash, 0, None, b""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uid = state.bucket.has_hash(&hash).unwrap();
+        let item = state.bucket.get(&uid).unwrap();
+        assert_eq!(*item.get_size(), 0);
+        assert_eq!(
+            std::fs::metadata(state.bucket.get_resource_path(&item))
+                .unwrap()
+                .len(),
+            0
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_other_extension() {
+        let dir = std::env::temp_dir().join(format!("synclink-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = AppState::for_test(&dir).await;
+        let app =
+            crate::routes::routes(tower_http::cors::CorsLayer::new()).with_state(state.clone());
+        let hash = sha256(b"hello world");
+        let response = app
+            .clone()
+            .oneshot(request("a.txt", &hash, 11, None, b"hello"))
+            .await
+            .unwrap();
+        assert!(response.status().is_server_error());
+        // the partial file is stored as .txt, it can't become the .md file
+        let response = app
+            .clone()
+            .oneshot(request("a.md", &hash, 6, Some("bytes 5-10/11"), b" world"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        let response = app
+            .oneshot(request("a.txt", &hash, 6, Some("bytes 5-10/11"), b" world"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uid = state.bucket.has_hash(&hash).unwrap();
+        let item = state.bucket.get(&uid).unwrap();
+        assert_eq!(
+            std::fs::read(state.bucket.get_resource_path(&item)).unwrap(),
+            b"hello world"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::AppState;
+use crate::config::{AppState, Durability};
 use crate::errors::{ApiError, InternalError};
 use crate::models::bucket::BucketAction;
 use crate::utils::{HttpException, HttpResult};
@@ -6,7 +6,7 @@ use crate::{throw_error, try_break_ok, utils};
 use anyhow::Context;
 use axum::{
     debug_handler,
-    extract::{BodyStream, Path, Query, State},
+    extract::{BodyStream, ConnectInfo, Path, Query, State},
     http::{HeaderMap, StatusCode},
     response::{AppendHeaders, IntoResponse},
     Json,
@@ -70,13 +70,14 @@ where
 
 /// allocate disk resource
 async fn allocate(uid: &Uuid, parts: Vec<u64>) -> anyhow::Result<()> {
-    let path = std::env::temp_dir().join("synclink");
-    fs::create_dir(&path).await?;
+    let path = crate::models::upload_session::parts_dir();
+    fs::create_dir_all(&path).await?;
     for (pos, size) in parts.iter().enumerate() {
         let path = path.join(format!("{}.part.{}", uid, pos));
         let file = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&path)
             .await
             .with_context(|| InternalError::OpenFile(&path).to_string())?;
@@ -88,8 +89,13 @@ async fn allocate(uid: &Uuid, parts: Vec<u64>) -> anyhow::Result<()> {
 }
 
 /// append chunks
-async fn append(uid: &Uuid, stream: &mut BodyStream, pos: u32) -> anyhow::Result<()> {
-    let path = std::env::temp_dir().join("synclink");
+async fn append(
+    uid: &Uuid,
+    stream: &mut BodyStream,
+    pos: u32,
+    durability: Durability,
+) -> anyhow::Result<()> {
+    let path = crate::models::upload_session::parts_dir();
     let path = path.join(format!("{}.part.{}", uid, pos));
     let mut file = fs::OpenOptions::new()
         .write(true)
@@ -100,48 +106,95 @@ async fn append(uid: &Uuid, stream: &mut BodyStream, pos: u32) -> anyhow::Result
         file.write_all(chunk.with_context(|| InternalError::ReadStream)?.as_ref())
             .await
             .with_context(|| InternalError::WriteFile(&path).to_string())?;
+        if durability >= Durability::PerChunk {
+            file.sync_data()
+                .await
+                .with_context(|| InternalError::WriteFile(&path).to_string())?;
+        }
+    }
+    if durability >= Durability::PerPart {
+        file.sync_data()
+            .await
+            .with_context(|| InternalError::WriteFile(&path).to_string())?;
     }
     Ok(())
 }
 
-/// concatenate chunks
+/// Position of a part file named `<prefix><pos>`
+fn part_position(filename: &str, prefix: &str) -> Option<u32> {
+    filename.strip_prefix(prefix)?.parse().ok()
+}
+
+/// Path of a file being finalized, removed when dropped unless kept, so an upload failing or
+/// cancelled before it is indexed leaves no file behind
+struct UnindexedFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl UnindexedFile {
+    fn new(path: PathBuf) -> Self {
+        Self { path, keep: false }
+    }
+    fn keep(mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for UnindexedFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Concatenate the parts into the stored file, the parts are left for the caller to remove
+/// once the file is indexed so the upload can be retried until then
 async fn concatenate(
-    storage_path: &std::path::Path,
+    bucket: &crate::models::Bucket,
     uid: &Uuid,
     filename: &Option<String>,
-) -> anyhow::Result<(PathBuf, usize, String)> {
-    use sha2::{Digest, Sha256};
+    durability: Durability,
+    algorithm: utils::HashAlgorithm,
+) -> anyhow::Result<(UnindexedFile, usize, String)> {
     use tokio_util::io::ReaderStream;
 
-    // retrieving path of part files
+    // retrieving path of part files, in part order since the directory listing isn't sorted
     let mut parts = Vec::new();
-    let path = std::env::temp_dir().join("synclink");
+    let path = crate::models::upload_session::parts_dir();
     let prefix = format!("{}.part.", uid);
     for entry in std::fs::read_dir(&path)? {
         let entry = entry?;
         let path = entry.path();
         let filename = path.file_name().and_then(|it| it.to_str()).unwrap_or("");
-        if filename.starts_with(&prefix) && path.is_file() {
-            parts.push(path)
+        if let Some(pos) = part_position(filename, &prefix) {
+            if path.is_file() {
+                parts.push((pos, path))
+            }
         }
     }
+    parts.sort_unstable_by_key(|(pos, _)| *pos);
     // create dst file
     let ext = filename
         .as_ref()
         .map(std::path::Path::new)
         .and_then(|it| it.extension())
-        .map(|it| format!(".{}", it.to_string_lossy()))
-        .unwrap_or("".to_string());
-    let temp = path.join(format!("{}{}.part", uid, ext));
+        .map(|it| it.to_string_lossy().to_string());
+    let temp = path.join(match &ext {
+        Some(ext) => format!("{}.{}.part", uid, ext),
+        None => format!("{}.part", uid),
+    });
     let mut dst = fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(&temp)
         .await?;
-    let mut hasher = Sha256::new();
+    let temp = UnindexedFile::new(temp);
+    let mut hasher = algorithm.hasher();
     let mut size = 0;
-    // copy and delete
-    for part in parts {
+    for (_, part) in parts {
         let src = fs::File::open(&part)
             .await
             .with_context(|| InternalError::OpenFile(&path).to_string())?;
@@ -153,21 +206,32 @@ async fn concatenate(
             dst.write_all(&chunk)
                 .await
                 .with_context(|| InternalError::WriteFile(&part).to_string())?;
+            if durability >= Durability::PerChunk {
+                dst.sync_data()
+                    .await
+                    .with_context(|| InternalError::WriteFile(&temp.path).to_string())?;
+            }
         }
-        fs::remove_file(&part)
+    }
+    if durability >= Durability::OnFinalize {
+        dst.sync_data()
             .await
-            .with_context(|| InternalError::DeleteFile(&part).to_string())?;
+            .with_context(|| InternalError::WriteFile(&temp.path).to_string())?;
+    }
+    let path = bucket.resource_path(uid, ext.as_deref());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
     }
-    let path = storage_path.join(format!("{}{}", uid, ext));
-    fs::rename(&temp, &path)
+    fs::rename(&temp.path, &path)
         .await
-        .with_context(|| InternalError::RenameFile(&temp, &path).to_string())?;
-    Ok((path, size, format!("{:x}", hasher.finalize())))
+        .with_context(|| InternalError::RenameFile(&temp.path, &path).to_string())?;
+    temp.keep();
+    Ok((UnindexedFile::new(path), size, hasher.finalize()))
 }
 
 /// cleanup uploaded chunks
 async fn cleanup(uid: &Uuid) -> anyhow::Result<()> {
-    let path = std::env::temp_dir().join("synclink");
+    let path = crate::models::upload_session::parts_dir();
     let prefix = format!("{}.part", uid); // part files and temp file
     for entry in std::fs::read_dir(&path)? {
         let entry = entry?;
@@ -185,6 +249,7 @@ async fn cleanup(uid: &Uuid) -> anyhow::Result<()> {
 #[debug_handler]
 pub async fn upload_part(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
     id: Option<Path<Uuid>>,
     query: Query<QueryParams>,
     headers: HeaderMap,
@@ -192,6 +257,7 @@ pub async fn upload_part(
 ) -> HttpResult<impl IntoResponse> {
     let query: QueryParams = query.0;
     let uid: Option<Uuid> = id.map(|it| it.0);
+    let durability = state.config.file_storage.durability;
     match query.act {
         Action::Allocate => {
             let content_hash = try_break_ok!(headers
@@ -201,6 +267,31 @@ pub async fn upload_part(
                     HttpException::BadRequest,
                     ApiError::HeaderFieldMissing("X-Content-Sha256")
                 )));
+            let algorithm = state.config.file_storage.hash;
+            if utils::HashAlgorithm::of(&content_hash) != Some(algorithm) {
+                throw_error!(
+                    HttpException::BadRequest,
+                    ApiError::UnexpectedHashAlgorithm {
+                        expected: algorithm.name()
+                    }
+                )
+            }
+            // fail fast on what the client declares, if it does
+            let filename = headers
+                .get("x-raw-filename")
+                .and_then(|it| it.to_str().ok())
+                .and_then(|it| utils::decode_uri(it).ok());
+            let content_type = headers.get("content-type").and_then(|it| it.to_str().ok());
+            if state
+                .config
+                .blocklist
+                .blocks(filename.as_deref(), content_type)
+            {
+                throw_error!(
+                    HttpException::UnsupportedMediaType,
+                    ApiError::ForbiddenContentType
+                )
+            }
             if let Some(uuid) = state.bucket.has_hash(&content_hash) {
                 return Ok::<_, ()>(
                     (
@@ -218,7 +309,33 @@ pub async fn upload_part(
                     ApiError::QueryFieldMissing("parts")
                 )
             }
-            try_break_ok!(allocate(&uid, query.parts.unwrap()).await);
+            let parts = query.parts.unwrap();
+            if !state
+                .bucket
+                .has_room(parts.iter().sum(), state.config.upload.reserved_space)
+            {
+                throw_error!(
+                    HttpException::InsufficientStorage,
+                    ApiError::DiskQuotaExceeded
+                )
+            }
+            // reserved before any file is created, so concurrent allocations can't overshoot
+            let limit = state.config.upload.max_sessions_per_client;
+            if !state
+                .upload_sessions
+                .create(uid, content_hash, parts.clone(), addr.ip(), limit)
+            {
+                throw_error!(
+                    HttpException::TooManyRequests,
+                    ApiError::TooManySessions {
+                        limit: limit.unwrap_or_default()
+                    }
+                )
+            }
+            if let Err(err) = allocate(&uid, parts).await {
+                state.upload_sessions.remove(&uid);
+                return Err(err).into();
+            }
             Ok::<_, ()>((StatusCode::CREATED, Json(uid.to_string())).into_response()).into()
         }
         Action::Append => {
@@ -233,7 +350,15 @@ pub async fn upload_part(
                     ApiError::QueryFieldMissing("pos")
                 ),
             };
-            try_break_ok!(append(&uid, &mut stream, pos).await);
+            // the part file is rewritten from the start on retry, so it is fine to abandon it
+            tokio::select! {
+                result = append(&uid, &mut stream, pos, durability) => try_break_ok!(result),
+                _ = state.shutdown.abort.cancelled() => throw_error!(
+                    HttpException::ServiceUnavailable,
+                    ApiError::ServerShuttingDown
+                ),
+            }
+            state.upload_sessions.mark_received(&uid, pos);
             Ok::<_, ()>(Json("ok!".to_string()).into_response()).into()
         }
         Action::Concatenate => {
@@ -263,21 +388,63 @@ pub async fn upload_part(
                 .get("user-agent")
                 .and_then(|it| it.to_str().ok())
                 .map(|it| it.to_string());
+            if state
+                .config
+                .blocklist
+                .blocks(filename.as_deref(), Some(&content_type))
+            {
+                try_break_ok!(cleanup(&uid).await);
+                state.upload_sessions.remove(&uid);
+                throw_error!(
+                    HttpException::UnsupportedMediaType,
+                    ApiError::ForbiddenContentType
+                )
+            }
 
-            let (path, size, hash) =
-                try_break_ok!(concatenate(state.bucket.get_storage_path(), &uid, &filename).await);
+            // until the file is indexed, a failure or a dropped connection removes it and
+            // leaves the parts and the session for a retry
+            let (file, size, hash) = try_break_ok!(
+                concatenate(
+                    &state.bucket,
+                    &uid,
+                    &filename,
+                    durability,
+                    state.config.file_storage.hash
+                )
+                .await
+            );
             if content_hash != hash {
-                try_break_ok!(fs::remove_file(&path)
-                    .await
-                    .with_context(|| InternalError::Cleanup));
+                drop(file);
+                try_break_ok!(cleanup(&uid).await);
+                state.upload_sessions.remove(&uid);
                 throw_error!(HttpException::BadRequest, ApiError::HashMismatch)
             }
+            if !state.config.blocklist.mimetypes.is_empty() {
+                let sniffed = try_break_ok!(utils::sniff_mimetype(&file.path)
+                    .await
+                    .with_context(|| InternalError::OpenFile(&file.path).to_string()));
+                if sniffed.is_some_and(|it| state.config.blocklist.blocks_mimetype(&it)) {
+                    drop(file);
+                    try_break_ok!(cleanup(&uid).await);
+                    state.upload_sessions.remove(&uid);
+                    throw_error!(
+                        HttpException::UnsupportedMediaType,
+                        ApiError::ForbiddenContentType
+                    )
+                }
+            }
             try_break_ok!(
                 state
                     .bucket
                     .write(uid, user_agent, filename, content_type, hash, size)
                     .await
             );
+            file.keep();
+            state.upload_sessions.remove(&uid);
+            // leftovers are swept with the other parts without a session
+            if let Err(err) = cleanup(&uid).await {
+                tracing::warn!(?err, "{}", InternalError::Cleanup);
+            }
             if let Err(err) = state.broadcast.send(BucketAction::Add(uid)) {
                 tracing::warn!(%err, "{}", InternalError::Broadcast(&format!("add {} action", uid)));
             }
@@ -289,7 +456,45 @@ pub async fn upload_part(
                 None => throw_error!(HttpException::BadRequest, ApiError::PathParameterMissing),
             };
             try_break_ok!(cleanup(&uid).await);
+            state.upload_sessions.remove(&uid);
             Ok::<_, ()>(Json("ok!".to_string()).into_response()).into()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part_position() {
+        let prefix = "0dfe8ccc-45c6-44ae-91f4-655af2df4a65.part.";
+        let mut names = [10, 2, 0, 1].map(|pos| format!("{}{}", prefix, pos));
+        names.sort_unstable_by_key(|it| part_position(it, prefix));
+        assert_eq!(
+            names.map(|it| part_position(&it, prefix)),
+            [0, 1, 2, 10].map(Some)
+        );
+        // the temp files of a concatenation aren't parts
+        assert_eq!(
+            part_position("0dfe8ccc-45c6-44ae-91f4-655af2df4a65.part", prefix),
+            None
+        );
+        assert_eq!(
+            part_position("0dfe8ccc-45c6-44ae-91f4-655af2df4a65.txt.part", prefix),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unindexed_file() {
+        let path = std::env::temp_dir().join(format!("{}.unindexed", Uuid::new_v4()));
+        std::fs::write(&path, b"x").unwrap();
+        drop(UnindexedFile::new(path.clone()));
+        assert!(!path.exists());
+        std::fs::write(&path, b"x").unwrap();
+        UnindexedFile::new(path.clone()).keep();
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,9 +3,17 @@ use axum::{
     debug_handler,
     extract::State,
     http::{header, HeaderMap, StatusCode},
-    response::{AppendHeaders, IntoResponse},
+    response::{AppendHeaders, IntoResponse, Response},
 };
 
+/// Tell the client in one round-trip whether the content is
+/// - already stored: `409` with `Location` and `X-Exists-Id`
+/// - partially uploaded through `upload-part`: `202` with `X-Upload-Id` and `X-Resume-Offset`,
+///   the offset covers the leading parts that were appended completely
+/// - partially uploaded through an interrupted `upload`: `202` with `X-Resume-Offset` only
+/// - new: `200`
+///
+/// Every answer names the algorithm the content must be hashed with in `X-Hash-Algorithm`.
 #[debug_handler]
 pub async fn upload_preflight(
     State(state): State<AppState>,
@@ -15,12 +23,57 @@ pub async fn upload_preflight(
         .get("x-content-sha256")
         .map(|it| String::from_utf8_lossy(it.as_bytes()).to_lowercase())
         .unwrap_or_default();
-    match state.bucket.has_hash(&content_hash) {
-        Some(uid) => (
+    let content_length = headers
+        .get("x-content-length")
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.parse::<u64>().ok());
+    let response = if let Some(uid) = state.bucket.has_hash(&content_hash) {
+        (
             StatusCode::CONFLICT,
-            AppendHeaders([(header::LOCATION, uid.to_string())]),
+            AppendHeaders([
+                (header::LOCATION, uid.to_string()),
+                (
+                    header::HeaderName::from_static("x-exists-id"),
+                    uid.to_string(),
+                ),
+            ]),
+        )
+            .into_response()
+    } else {
+        preflight_session(&state, &content_hash, content_length)
+    };
+    (
+        AppendHeaders([("x-hash-algorithm", state.config.file_storage.hash.name())]),
+        response,
+    )
+}
+
+fn preflight_session(
+    state: &AppState,
+    content_hash: &str,
+    content_length: Option<u64>,
+) -> Response {
+    match state.upload_sessions.find(content_hash, content_length) {
+        Some((uid, offset)) => (
+            StatusCode::ACCEPTED,
+            AppendHeaders([
+                ("x-upload-id", uid.to_string()),
+                ("x-resume-offset", offset.to_string()),
+            ]),
         )
             .into_response(),
-        None => StatusCode::OK.into_response(),
+        None => match state
+            .upload_sessions
+            .interrupted_offset(content_hash, content_length)
+        {
+            // an interrupted single-shot upload has no id, the client retries its `upload`
+            // with a `Content-Range` starting at the offset
+            Some(offset) => (
+                StatusCode::ACCEPTED,
+                AppendHeaders([("x-resume-offset", offset.to_string())]),
+            )
+                .into_response(),
+            None => StatusCode::OK.into_response(),
+        },
     }
 }
@@ -0,0 +1,32 @@
+use crate::config::AppState;
+use axum::{debug_handler, extract::State, Json};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug)]
+pub struct ProbeBody {
+    /// see `utils::probe_hash`
+    probe: String,
+    size: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProbeResultDto {
+    /// entry that likely holds the same content
+    id: Option<Uuid>,
+}
+
+/// Look for a likely duplicate by the quick fingerprint, so a client can skip hashing a large
+/// file in full before deciding to upload it.
+///
+/// A match is only a hint, the client confirms it with the full hash through the preflight.
+#[debug_handler]
+pub async fn upload_probe(
+    State(state): State<AppState>,
+    Json(body): Json<ProbeBody>,
+) -> Json<ProbeResultDto> {
+    let probe = body.probe.to_lowercase();
+    Json(ProbeResultDto {
+        id: state.bucket.find_by_probe(&probe, body.size),
+    })
+}
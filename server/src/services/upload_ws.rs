@@ -0,0 +1,294 @@
+use super::upload::ResumeGuard;
+use crate::config::{AppState, Durability};
+use crate::errors::{ApiError, InternalError};
+use crate::models::bucket::{BucketAction, PreallocationFile};
+use crate::models::upload_session::InterruptedUpload;
+use crate::utils::{self, HttpException};
+use anyhow::Context;
+use axum::{
+    debug_handler,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Largest binary frame, the same bound as a part of `upload-part`
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// First message of the client, as a text frame
+#[derive(Deserialize)]
+struct Handshake {
+    size: u64,
+    /// same as `X-Content-Sha256`
+    hash: String,
+    filename: Option<String>,
+    mimetype: String,
+}
+
+/// Text frames sent by the server
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Reply {
+    /// send the content from `offset` on, past 0 when an interrupted upload is continued
+    Ready {
+        offset: u64,
+    },
+    /// bytes received and written so far, a client reconnecting after a drop resumes from
+    /// the last acknowledged offset
+    Ack {
+        offset: u64,
+    },
+    /// the content is stored already, nothing is uploaded
+    Exists {
+        uid: Uuid,
+    },
+    Done {
+        uid: Uuid,
+    },
+    Error {
+        code: &'static str,
+        message: String,
+        details: Option<serde_json::Value>,
+    },
+}
+
+impl From<ApiError<'_>> for Reply {
+    fn from(err: ApiError<'_>) -> Self {
+        Reply::Error {
+            code: err.code(),
+            message: err.to_string(),
+            details: err.details(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Reply {
+    fn from(err: anyhow::Error) -> Self {
+        tracing::error!(?err, "websocket upload failed");
+        Reply::Error {
+            code: HttpException::InternalError.code(),
+            message: "Something went wrong".to_string(),
+            details: None,
+        }
+    }
+}
+
+/// Upload over a WebSocket, for networks whose proxies break long HTTP uploads.
+///
+/// The client opens with a [`Handshake`], then sends the content as sequential binary frames,
+/// each acknowledged with its offset. The upload completes once `size` bytes arrived. A
+/// dropped connection is kept like an interrupted `upload`, a new handshake for the same
+/// content, over either transport, continues it.
+#[debug_handler]
+pub async fn upload_ws(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|it| it.to_str().ok())
+        .map(|it| it.to_string());
+    ws.max_frame_size(MAX_FRAME_SIZE)
+        .max_message_size(MAX_FRAME_SIZE)
+        .on_upgrade(move |mut socket| async move {
+            let reply = match receive(&state, &mut socket, user_agent).await {
+                Ok(Some(reply)) | Err(reply) => reply,
+                // the connection is gone
+                Ok(None) => return,
+            };
+            let _ = send(&mut socket, &reply).await;
+            let _ = socket.close().await;
+        })
+}
+
+async fn send(socket: &mut WebSocket, reply: &Reply) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(reply).expect("a reply serializes");
+    socket.send(Message::Text(text)).await
+}
+
+/// Run the upload, the reply is the last frame sent before closing
+async fn receive(
+    state: &AppState,
+    socket: &mut WebSocket,
+    user_agent: Option<String>,
+) -> Result<Option<Reply>, Reply> {
+    let handshake = loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str::<Handshake>(&text) {
+                Ok(handshake) => break handshake,
+                Err(_) => return Err(ApiError::BodyFieldMissing("handshake").into()),
+            },
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            Some(Ok(Message::Binary(_))) => {
+                return Err(ApiError::BodyFieldMissing("handshake").into())
+            }
+            Some(Ok(Message::Close(_)) | Err(_)) | None => return Ok(None),
+        }
+    };
+    let hash = handshake.hash.to_lowercase();
+    let algorithm = state.config.file_storage.hash;
+    if utils::HashAlgorithm::of(&hash) != Some(algorithm) {
+        return Err(ApiError::UnexpectedHashAlgorithm {
+            expected: algorithm.name(),
+        }
+        .into());
+    }
+    if state
+        .config
+        .blocklist
+        .blocks(handshake.filename.as_deref(), Some(&handshake.mimetype))
+    {
+        return Err(ApiError::ForbiddenContentType.into());
+    }
+    if let Some(uid) = state.bucket.has_hash(&hash) {
+        return Ok(Some(Reply::Exists { uid }));
+    }
+    let (mut preallocation, upload) = match state.upload_sessions.take_interrupted(&hash) {
+        // the extension of the stored file comes from the filename, a handshake under another
+        // extension would be indexed at a path the partial file isn't at
+        Some(upload)
+            if upload.total == handshake.size
+                && upload.path == state.bucket.upload_path(&upload.uid, &handshake.filename) =>
+        {
+            if !state.bucket.has_room(
+                upload.total - upload.written,
+                state.config.upload.reserved_space,
+            ) {
+                state.upload_sessions.restore_interrupted(hash, upload);
+                return Err(ApiError::DiskQuotaExceeded.into());
+            }
+            let file = match super::upload::reopen(&upload).await {
+                Ok(file) => file,
+                Err(err) => {
+                    state.upload_sessions.restore_interrupted(hash, upload);
+                    return Err(err.into());
+                }
+            };
+            let preallocation = PreallocationFile {
+                uid: upload.uid,
+                file,
+                path: upload.path.clone(),
+            };
+            (preallocation, upload)
+        }
+        interrupted => {
+            // an interrupted upload of another size can't be the same content, one under
+            // another extension starts over
+            if let Some(upload) = interrupted {
+                let _ = tokio::fs::remove_file(&upload.path).await;
+            }
+            if !state
+                .bucket
+                .has_room(handshake.size, state.config.upload.reserved_space)
+            {
+                return Err(ApiError::DiskQuotaExceeded.into());
+            }
+            let preallocation = state
+                .bucket
+                .preallocation(&handshake.filename, &Some(handshake.size))
+                .await?;
+            let upload = InterruptedUpload::new(
+                preallocation.uid,
+                preallocation.path.clone(),
+                algorithm.hasher(),
+                handshake.size,
+            );
+            (preallocation, upload)
+        }
+    };
+    let durability = state.config.file_storage.durability;
+    let mut guard = ResumeGuard::new(state.upload_sessions.clone(), hash.clone(), upload);
+    let offset = guard.upload().written;
+    if send(socket, &Reply::Ready { offset }).await.is_err() {
+        return Ok(None);
+    }
+    while guard.upload().written < handshake.size {
+        let message = tokio::select! {
+            message = socket.recv() => message,
+            _ = state.shutdown.abort.cancelled() => {
+                guard.disarm();
+                preallocation.cleanup().await?;
+                return Err(ApiError::ServerShuttingDown.into());
+            }
+        };
+        // the guard keeps what was received so far for a reconnect
+        let chunk = match message {
+            Some(Ok(Message::Binary(chunk))) => chunk,
+            Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Text(_))) => continue,
+            Some(Ok(Message::Close(_)) | Err(_)) | None => return Ok(None),
+        };
+        if guard.upload().written + chunk.len() as u64 > handshake.size {
+            guard.disarm();
+            preallocation.cleanup().await?;
+            return Err(ApiError::RangeTooLarge.into());
+        }
+        let written = preallocation
+            .file
+            .write_all(&chunk)
+            .await
+            .with_context(|| InternalError::WriteFile(&preallocation.path).to_string());
+        if let Err(err) = written {
+            guard.disarm();
+            preallocation.cleanup().await?;
+            return Err(err.into());
+        }
+        if durability >= Durability::PerChunk {
+            preallocation
+                .file
+                .sync_data()
+                .await
+                .with_context(|| InternalError::WriteFile(&preallocation.path).to_string())?;
+        }
+        let upload = guard.upload();
+        upload.hasher.update(&chunk);
+        upload.written += chunk.len() as u64;
+        let offset = upload.written;
+        if send(socket, &Reply::Ack { offset }).await.is_err() {
+            return Ok(None);
+        }
+    }
+    let upload = guard.disarm();
+    if upload.hasher.finalize() != hash {
+        preallocation.cleanup().await?;
+        return Err(ApiError::HashMismatch.into());
+    }
+    if !state.config.blocklist.mimetypes.is_empty() {
+        let sniffed = utils::sniff_mimetype(&preallocation.path)
+            .await
+            .with_context(|| InternalError::OpenFile(&preallocation.path).to_string())?;
+        if sniffed.is_some_and(|it| state.config.blocklist.blocks_mimetype(&it)) {
+            preallocation.cleanup().await?;
+            return Err(ApiError::ForbiddenContentType.into());
+        }
+    }
+    if durability >= Durability::OnFinalize {
+        preallocation
+            .file
+            .sync_data()
+            .await
+            .with_context(|| InternalError::WriteFile(&preallocation.path).to_string())?;
+    }
+    let uid = preallocation.uid;
+    state
+        .bucket
+        .write(
+            uid,
+            user_agent,
+            handshake.filename,
+            handshake.mimetype,
+            hash,
+            upload.written as usize,
+        )
+        .await?;
+    if let Err(err) = state.broadcast.send(BucketAction::Add(uid)) {
+        tracing::warn!(%err, "{}", InternalError::Broadcast(&format!("add {} action", uid)));
+    }
+    Ok(Some(Reply::Done { uid }))
+}
@@ -0,0 +1,57 @@
+use crate::config::state::AppState;
+use crate::errors::{ApiError, InternalError};
+use crate::utils::{HttpException, HttpResult};
+use crate::{throw_error, utils};
+use anyhow::Context;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Serialize, Debug)]
+pub struct VerifyResultDto {
+    ok: bool,
+    expected: String,
+    actual: String,
+}
+
+/// Re-hash the stored file and compare it with the hash recorded at upload
+#[debug_handler]
+pub async fn verify(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> HttpResult<Json<VerifyResultDto>> {
+    let bucket = state.bucket;
+    let item = match bucket.get(&id) {
+        Some(item) => item,
+        None => throw_error!(HttpException::NotFound, ApiError::ResourceNotFound),
+    };
+    let path = bucket.get_resource_path(&item);
+    // a store can hold hashes of an algorithm no longer configured, compare like with like
+    let algorithm = utils::HashAlgorithm::of(item.get_hash()).unwrap_or_default();
+    // hashing a large file is blocking work, keep it off the async workers
+    let actual = tokio::task::spawn_blocking(move || -> anyhow::Result<String> {
+        let mut file = std::fs::File::open(&path)
+            .with_context(|| InternalError::OpenFile(&path).to_string())?;
+        let mut hasher = algorithm.hasher();
+        std::io::copy(&mut file, &mut hasher).with_context(|| InternalError::ReadStream)?;
+        Ok(hasher.finalize())
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|it| it);
+    let actual = match actual {
+        Ok(actual) => actual,
+        Err(err) => return Err(err).into(),
+    };
+    let expected = item.get_hash().to_string();
+    Ok::<_, ()>(Json(VerifyResultDto {
+        ok: expected == actual,
+        expected,
+        actual,
+    }))
+    .into()
+}
@@ -0,0 +1,228 @@
+use crate::config::LogFormat;
+use axum::body::{BoxBody, Bytes, HttpBody};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap, Request};
+use axum::{middleware::Next, response::Response};
+use http_body::SizeHint;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+/// Access log file, one line per request apart from the application log.
+///
+/// Lines are handed to a task appending them to the file, requests never wait on the disk.
+pub struct AccessLog {
+    sender: mpsc::UnboundedSender<String>,
+    format: LogFormat,
+}
+
+impl AccessLog {
+    pub fn open(path: &std::path::Path, format: LogFormat) -> std::io::Result<Self> {
+        use tokio::io::AsyncWriteExt;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            let mut file = tokio::fs::File::from_std(file);
+            while let Some(line) = receiver.recv().await {
+                let mut lines = line;
+                while let Ok(line) = receiver.try_recv() {
+                    lines.push_str(&line);
+                }
+                let written = file.write_all(lines.as_bytes()).await;
+                if let Err(err) = written.and(file.flush().await) {
+                    tracing::warn!(%err, "Failed to write access log");
+                }
+            }
+        });
+        Ok(Self { sender, format })
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct AccessEntry {
+    #[serde(serialize_with = "rfc3339")]
+    time: chrono::DateTime<chrono::Local>,
+    client_ip: String,
+    forwarded_for: Option<String>,
+    /// there are no accounts, always absent for now
+    user: Option<String>,
+    method: String,
+    path: String,
+    version: String,
+    status: u16,
+    /// body bytes sent, less than the length when the client went away early
+    bytes: u64,
+    /// from the request until the body was done
+    latency_ms: u128,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessEntry {
+    /// Combined Log Format followed by the latency, or a JSON object
+    fn to_line(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Text => {
+                let quoted =
+                    |it: &Option<String>| it.as_deref().unwrap_or("-").replace('"', "\\\"");
+                format!(
+                    "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {}ms\n",
+                    self.client_ip,
+                    self.user.as_deref().unwrap_or("-"),
+                    self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                    self.method,
+                    self.path,
+                    self.version,
+                    self.status,
+                    self.bytes,
+                    quoted(&self.referer),
+                    quoted(&self.user_agent),
+                    self.latency_ms
+                )
+            }
+            LogFormat::Json => {
+                let mut line = serde_json::to_string(self).expect("an entry serializes");
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+fn rfc3339<S: serde::Serializer>(
+    time: &chrono::DateTime<chrono::Local>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.to_rfc3339())
+}
+
+/// Response body counting what is sent, the entry is logged once it is done or dropped
+struct CountedBody {
+    inner: BoxBody,
+    entry: AccessEntry,
+    start: Instant,
+    log: Arc<AccessLog>,
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        self.entry.latency_ms = self.start.elapsed().as_millis();
+        let _ = self.log.sender.send(self.entry.to_line(self.log.format));
+    }
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &poll {
+            self.entry.bytes += data.len() as u64;
+        }
+        poll
+    }
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Middleware writing an access log line per request
+pub async fn access_log<B>(
+    State(log): State<Arc<AccessLog>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let header = |name: header::HeaderName| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|it| it.to_str().ok())
+            .map(|it| it.to_string())
+    };
+    let entry = AccessEntry {
+        time: chrono::Local::now(),
+        client_ip: addr.ip().to_string(),
+        forwarded_for: header(header::HeaderName::from_static("x-forwarded-for")),
+        user: None,
+        method: request.method().to_string(),
+        path: request
+            .uri()
+            .path_and_query()
+            .map_or_else(|| request.uri().path().to_string(), |it| it.to_string()),
+        version: format!("{:?}", request.version()),
+        status: 0,
+        bytes: 0,
+        latency_ms: 0,
+        referer: header(header::REFERER),
+        user_agent: header(header::USER_AGENT),
+    };
+    let response = next.run(request).await;
+    let status = response.status().as_u16();
+    response.map(|inner| {
+        axum::body::boxed(CountedBody {
+            inner,
+            entry: AccessEntry { status, ..entry },
+            start,
+            log,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_access_entry_line() {
+        let entry = AccessEntry {
+            time: chrono::Local.timestamp_opt(0, 0).unwrap(),
+            client_ip: "127.0.0.1".to_string(),
+            forwarded_for: None,
+            user: None,
+            method: "GET".to_string(),
+            path: "/api?page=2".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes: 512,
+            latency_ms: 3,
+            referer: None,
+            user_agent: Some("curl \"8\"".to_string()),
+        };
+        let text = entry.to_line(LogFormat::Text);
+        assert!(text.starts_with("127.0.0.1 - - ["), "{}", text);
+        assert!(
+            text.ends_with("] \"GET /api?page=2 HTTP/1.1\" 200 512 \"-\" \"curl \\\"8\\\"\" 3ms\n"),
+            "{}",
+            text
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&entry.to_line(LogFormat::Json)).unwrap();
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["bytes"], 512);
+        assert_eq!(json["path"], "/api?page=2");
+        assert_eq!(json["user"], serde_json::Value::Null);
+    }
+}
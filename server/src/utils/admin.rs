@@ -0,0 +1,24 @@
+use super::{HttpError, HttpException};
+use crate::config::AdminConfig;
+use crate::errors::ApiError;
+use axum::http::HeaderMap;
+
+/// Check the `Access-Token` header against the configured admin token
+pub fn authorize_admin(admin: Option<&AdminConfig>, headers: &HeaderMap) -> Result<(), HttpError> {
+    let Some(admin) = admin else {
+        return Err((HttpException::Forbidden, ApiError::AdminDisabled).into());
+    };
+    let token = headers
+        .get("access-token")
+        .map(|it| it.as_bytes())
+        .unwrap_or_default();
+    if !constant_time_eq(token, admin.token.as_bytes()) {
+        return Err((HttpException::Unauthorized, ApiError::InvalidAccessToken).into());
+    }
+    Ok(())
+}
+
+/// Compare without short-circuiting, so the time taken doesn't leak the matching prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,306 @@
+//! Minimal streaming encoders for tar (ustar + PAX) and zip (stored, with data descriptors).
+//!
+//! Both only produce the framing around the file contents, so the contents can be streamed
+//! straight from disk without being buffered.
+
+const TAR_BLOCK: u64 = 512;
+
+/// Two zero blocks mark the end of a tar archive
+pub const TAR_END: [u8; 1024] = [0; 1024];
+
+/// Largest size the 11 octal digits of the ustar size field hold, 8 GiB - 1
+const TAR_MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+/// Header of a regular file, preceded by a PAX extended header when the name doesn't fit
+/// the 100 bytes of the ustar name field or the size doesn't fit its octal field. `mtime` is
+/// in milliseconds.
+pub fn tar_header(name: &str, size: u64, mtime: i64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(TAR_BLOCK as usize);
+    let long_name = name.len() > 100 || !name.is_ascii();
+    let mut records = Vec::new();
+    if long_name {
+        records.append(&mut pax_record("path", name));
+    }
+    // PAX readers take this size, GNU style readers the base-256 one of the ustar header
+    if size > TAR_MAX_OCTAL_SIZE {
+        records.append(&mut pax_record("size", &size.to_string()));
+    }
+    if !records.is_empty() {
+        let len = records.len() as u64;
+        bytes.extend(tar_block(b"PaxHeader", len, mtime, b'x'));
+        bytes.append(&mut records);
+        bytes.resize(bytes.len() + tar_padding(len) as usize, 0);
+    }
+    let ustar_name = if long_name {
+        // readers without PAX support still get a usable, truncated name
+        let ascii = name
+            .chars()
+            .map(|chr| if chr.is_ascii() { chr } else { '_' })
+            .collect::<String>();
+        ascii[ascii.len().saturating_sub(100)..].to_string()
+    } else {
+        name.to_string()
+    };
+    bytes.extend(tar_block(ustar_name.as_bytes(), size, mtime, b'0'));
+    bytes
+}
+
+/// Zero bytes needed after `size` bytes of content to fill the last block
+pub fn tar_padding(size: u64) -> u64 {
+    (TAR_BLOCK - size % TAR_BLOCK) % TAR_BLOCK
+}
+
+/// `"<len> <key>=<value>\n"` where `len` counts the whole record, itself included
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let body = format!(" {}={}\n", key, value);
+    let mut len = body.len() + 1;
+    while len != body.len() + len.to_string().len() {
+        len = body.len() + len.to_string().len();
+    }
+    format!("{}{}", len, body).into_bytes()
+}
+
+fn tar_block(name: &[u8], size: u64, mtime: i64, kind: u8) -> [u8; TAR_BLOCK as usize] {
+    fn octal(field: &mut [u8], value: u64) {
+        let digits = format!("{:0width$o}", value, width = field.len() - 1);
+        field[..digits.len()].copy_from_slice(digits.as_bytes());
+    }
+    let mut block = [0u8; TAR_BLOCK as usize];
+    block[..name.len().min(100)].copy_from_slice(&name[..name.len().min(100)]);
+    octal(&mut block[100..108], 0o644);
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    if size > TAR_MAX_OCTAL_SIZE {
+        // base-256, big-endian with the high bit of the first byte set
+        block[124] = 0x80;
+        block[128..136].copy_from_slice(&size.to_be_bytes());
+    } else {
+        octal(&mut block[124..136], size);
+    }
+    octal(&mut block[136..148], mtime.max(0) as u64 / 1000);
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    // the checksum is computed with its own field filled with spaces
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|it| *it as u32).sum();
+    let digits = format!("{:06o}\0 ", checksum);
+    block[148..156].copy_from_slice(digits.as_bytes());
+    block
+}
+
+struct ZipEntry {
+    name: String,
+    dos_time: (u16, u16),
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Zip writer storing files uncompressed, sizes and CRC follow each file in a data
+/// descriptor so nothing has to be known before the content is streamed.
+///
+/// Without zip64 the archive is limited to 4 GiB and 65535 entries, see [`ZipWriter::fits`].
+#[derive(Default)]
+pub struct ZipWriter {
+    offset: u64,
+    entries: Vec<ZipEntry>,
+}
+
+/// general purpose flags: sizes in a data descriptor, UTF-8 names
+const ZIP_FLAGS: u16 = 0x0808;
+
+impl ZipWriter {
+    /// Whether an archive of the given files can be written without zip64
+    pub fn fits(files: &[(&str, u64)]) -> bool {
+        files.len() <= u16::MAX as usize && Self::length(files) <= u32::MAX as u64
+    }
+    /// Exact length of the archive holding the given files
+    pub fn length(files: &[(&str, u64)]) -> u64 {
+        files
+            .iter()
+            .map(|(name, size)| 30 + 16 + 46 + 2 * name.len() as u64 + size)
+            .sum::<u64>()
+            + 22
+    }
+    /// Local header written before the content of a file, `mtime` is in milliseconds
+    pub fn begin_file(&mut self, name: &str, mtime: i64) -> Vec<u8> {
+        let dos_time = dos_time(mtime);
+        let mut bytes = Vec::with_capacity(30 + name.len());
+        bytes.extend(0x04034b50u32.to_le_bytes());
+        bytes.extend(20u16.to_le_bytes());
+        bytes.extend(ZIP_FLAGS.to_le_bytes());
+        // stored, no compression
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend(dos_time.0.to_le_bytes());
+        bytes.extend(dos_time.1.to_le_bytes());
+        // crc, compressed and uncompressed size follow in the data descriptor
+        bytes.extend([0u8; 12]);
+        bytes.extend((name.len() as u16).to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend(name.as_bytes());
+        self.entries.push(ZipEntry {
+            name: name.to_string(),
+            dos_time,
+            crc: 0,
+            size: 0,
+            offset: self.offset as u32,
+        });
+        self.offset += bytes.len() as u64;
+        bytes
+    }
+    /// Data descriptor written after the content of the current file
+    pub fn end_file(&mut self, crc: u32, size: u64) -> Vec<u8> {
+        let entry = self
+            .entries
+            .last_mut()
+            .expect("end_file called before begin_file");
+        entry.crc = crc;
+        entry.size = size as u32;
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend(0x08074b50u32.to_le_bytes());
+        bytes.extend(crc.to_le_bytes());
+        bytes.extend((size as u32).to_le_bytes());
+        bytes.extend((size as u32).to_le_bytes());
+        self.offset += size + bytes.len() as u64;
+        bytes
+    }
+    /// Central directory and end of central directory record
+    pub fn finish(self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for entry in &self.entries {
+            bytes.extend(0x02014b50u32.to_le_bytes());
+            bytes.extend(20u16.to_le_bytes());
+            bytes.extend(20u16.to_le_bytes());
+            bytes.extend(ZIP_FLAGS.to_le_bytes());
+            bytes.extend(0u16.to_le_bytes());
+            bytes.extend(entry.dos_time.0.to_le_bytes());
+            bytes.extend(entry.dos_time.1.to_le_bytes());
+            bytes.extend(entry.crc.to_le_bytes());
+            bytes.extend(entry.size.to_le_bytes());
+            bytes.extend(entry.size.to_le_bytes());
+            bytes.extend((entry.name.len() as u16).to_le_bytes());
+            // extra field, comment, disk number, internal and external attributes
+            bytes.extend([0u8; 12]);
+            bytes.extend(entry.offset.to_le_bytes());
+            bytes.extend(entry.name.as_bytes());
+        }
+        let count = (self.entries.len() as u16).to_le_bytes();
+        let directory_size = bytes.len() as u32;
+        bytes.extend(0x06054b50u32.to_le_bytes());
+        bytes.extend([0u8; 4]);
+        bytes.extend(count);
+        bytes.extend(count);
+        bytes.extend(directory_size.to_le_bytes());
+        bytes.extend((self.offset as u32).to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        bytes
+    }
+}
+
+/// MS-DOS (time, date) of a millisecond timestamp, clamped to the 1980 epoch
+fn dos_time(mtime: i64) -> (u16, u16) {
+    use chrono::{Datelike, TimeZone, Timelike};
+    let Some(date) = chrono::Utc.timestamp_millis_opt(mtime).single() else {
+        return (0, 0x21);
+    };
+    if date.year() < 1980 {
+        return (0, 0x21);
+    }
+    let time = ((date.hour() << 11) | (date.minute() << 5) | (date.second() / 2)) as u16;
+    let day = (((date.year() - 1980) as u32) << 9) | (date.month() << 5) | date.day();
+    (time, day as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_header() {
+        let header = tar_header("a.txt", 5, 0);
+        assert_eq!(header.len(), 512);
+        assert_eq!(&header[..5], b"a.txt");
+        assert_eq!(&header[124..136], b"00000000005\0");
+        assert_eq!(&header[257..262], b"ustar");
+        let checksum: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(idx, it)| {
+                if (148..156).contains(&idx) {
+                    32
+                } else {
+                    *it as u32
+                }
+            })
+            .sum();
+        assert_eq!(&header[148..156], format!("{:06o}\0 ", checksum).as_bytes());
+        assert_eq!(tar_padding(5), 507);
+        assert_eq!(tar_padding(1024), 0);
+    }
+
+    #[test]
+    fn test_tar_pax_header() {
+        let header = tar_header("文档.txt", 1, 0);
+        // PAX header block, one block of records, then the file header
+        assert_eq!(header.len(), 3 * 512);
+        assert_eq!(header[156], b'x');
+        assert_eq!(&header[512..531], "19 path=文档.txt\n".as_bytes());
+        assert_eq!(header[531], 0);
+        assert_eq!(header[1024 + 156], b'0');
+    }
+
+    #[test]
+    fn test_tar_large_size() {
+        let size = 10 * 1024 * 1024 * 1024;
+        let header = tar_header("a.bin", size, 0);
+        assert_eq!(header.len(), 3 * 512);
+        assert_eq!(header[156], b'x');
+        assert_eq!(&header[512..532], b"20 size=10737418240\n");
+        let ustar = &header[1024..];
+        assert_eq!(ustar[124], 0x80);
+        assert_eq!(ustar[125..128], [0; 3]);
+        assert_eq!(
+            u64::from_be_bytes(ustar[128..136].try_into().unwrap()),
+            size
+        );
+        // the largest octal size needs no PAX header
+        let header = tar_header("a.bin", TAR_MAX_OCTAL_SIZE, 0);
+        assert_eq!(header.len(), 512);
+        assert_eq!(&header[124..136], b"77777777777\0");
+        // a long name and a large size share one PAX header
+        let header = tar_header(&"a".repeat(120), size, 0);
+        assert_eq!(header.len(), 3 * 512);
+        let records = String::from_utf8_lossy(&header[512..1024]);
+        assert!(records.contains(" path=") && records.contains(" size=10737418240\n"));
+    }
+
+    #[test]
+    fn test_pax_record_length() {
+        // the length prefix growing a digit must be accounted for
+        let record = pax_record("path", &"a".repeat(90));
+        let len: usize = String::from_utf8_lossy(&record)
+            .split(' ')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(len, record.len());
+    }
+
+    #[test]
+    fn test_zip_length() {
+        let files = [("a.txt", 5u64), ("b.bin", 3)];
+        let mut writer = ZipWriter::default();
+        let mut length = 0;
+        for (name, size) in files {
+            length += writer.begin_file(name, 0).len() as u64;
+            length += size;
+            length += writer.end_file(0, size).len() as u64;
+        }
+        length += writer.finish().len() as u64;
+        assert_eq!(length, ZipWriter::length(&files));
+        assert!(ZipWriter::fits(&files));
+        assert!(!ZipWriter::fits(&[("big", u32::MAX as u64)]));
+    }
+}
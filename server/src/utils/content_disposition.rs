@@ -0,0 +1,65 @@
+/// Build a `Content-Disposition` value carrying both an ASCII `filename` fallback and an
+/// RFC 5987 `filename*` with the UTF-8 name, browsers prefer the latter when they understand it.
+pub fn content_disposition(kind: &str, filename: &str) -> String {
+    let fallback = filename
+        .chars()
+        .map(|chr| {
+            if chr.is_ascii() && !chr.is_ascii_control() {
+                chr
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        kind,
+        fallback,
+        encode_rfc5987(filename)
+    )
+}
+
+/// Percent-encode everything outside of the RFC 5987 `attr-char` set
+fn encode_rfc5987(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[test]
+fn test() {
+    assert_eq!(
+        content_disposition("attachment", "report.pdf"),
+        "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+    );
+    assert_eq!(
+        content_disposition("attachment", "my \"final\" 报告.txt"),
+        "attachment; filename=\"my \\\"final\\\" __.txt\"; \
+        filename*=UTF-8''my%20%22final%22%20%E6%8A%A5%E5%91%8A.txt"
+    );
+    assert_eq!(
+        content_disposition("inline", "a\\b\n.png"),
+        "inline; filename=\"a\\\\b_.png\"; filename*=UTF-8''a%5Cb%0A.png"
+    );
+}
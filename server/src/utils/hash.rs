@@ -0,0 +1,100 @@
+use serde::Deserialize;
+
+/// Algorithm identifying file content, used for deduplication and as the ETag.
+///
+/// SHA-256 hashes are stored as bare hex as they always were, other algorithms prefix theirs
+/// with their name, e.g. `blake3:<hex>`, so hashes of a mixed store never collide.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+    /// Algorithm a hash was produced with, `None` when it isn't a hash of a known algorithm
+    pub fn of(hash: &str) -> Option<Self> {
+        let (algorithm, hex) = match hash.split_once(':') {
+            Some(("blake3", hex)) => (HashAlgorithm::Blake3, hex),
+            Some(_) => return None,
+            None => (HashAlgorithm::Sha256, hash),
+        };
+        (hex.len() == 64 && hex.bytes().all(|it| it.is_ascii_hexdigit())).then_some(algorithm)
+    }
+    pub fn hasher(self) -> ContentHasher {
+        match self {
+            HashAlgorithm::Sha256 => ContentHasher::Sha256(sha2::Digest::new()),
+            HashAlgorithm::Blake3 => ContentHasher::Blake3(Box::default()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum ContentHasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ContentHasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            ContentHasher::Sha256(hasher) => sha2::Digest::update(hasher, bytes),
+            ContentHasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+    /// The hash in its stored form, see [`HashAlgorithm`]
+    pub fn finalize(self) -> String {
+        match self {
+            ContentHasher::Sha256(hasher) => format!("{:x}", sha2::Digest::finalize(hasher)),
+            ContentHasher::Blake3(hasher) => format!("blake3:{}", hasher.finalize().to_hex()),
+        }
+    }
+}
+
+impl std::io::Write for ContentHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_algorithm() {
+        let hash = |algorithm: HashAlgorithm| {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"hello");
+            hasher.finalize()
+        };
+        let sha256 = hash(HashAlgorithm::Sha256);
+        assert_eq!(
+            sha256,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        let blake3 = hash(HashAlgorithm::Blake3);
+        assert_eq!(
+            blake3,
+            "blake3:ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f"
+        );
+        assert_eq!(HashAlgorithm::of(&sha256), Some(HashAlgorithm::Sha256));
+        assert_eq!(HashAlgorithm::of(&blake3), Some(HashAlgorithm::Blake3));
+        assert_eq!(HashAlgorithm::of("abc"), None);
+        assert_eq!(HashAlgorithm::of(&sha256.replace('2', "g")), None);
+        assert_eq!(HashAlgorithm::of(&format!("md5:{}", &sha256[..32])), None);
+    }
+}
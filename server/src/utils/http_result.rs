@@ -2,6 +2,7 @@ use crate::errors::ApiError;
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
 
 #[allow(unused)]
@@ -19,25 +20,78 @@ pub enum HttpException {
     #[error("Not Found")]
     NotFound,
 
+    #[error("Precondition Failed")]
+    PreconditionFailed,
+
+    #[error("Payload Too Large")]
+    PayloadTooLarge,
+
+    #[error("Unsupported Media Type")]
+    UnsupportedMediaType,
+
     #[error("Range Not Satisfiable")]
     RangeNotSatisfiable,
 
+    #[error("Too Many Requests")]
+    TooManyRequests,
+
+    #[error("Service Unavailable")]
+    ServiceUnavailable,
+
     #[error("Internal Server Error")]
     InternalError,
+
+    #[error("Insufficient Storage")]
+    InsufficientStorage,
+}
+
+impl HttpException {
+    /// Fallback code for errors that carry no `ApiError`
+    pub fn code(&self) -> &'static str {
+        match self {
+            HttpException::BadRequest => "BAD_REQUEST",
+            HttpException::Unauthorized => "UNAUTHORIZED",
+            HttpException::Forbidden => "FORBIDDEN",
+            HttpException::NotFound => "NOT_FOUND",
+            HttpException::PreconditionFailed => "PRECONDITION_FAILED",
+            HttpException::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            HttpException::UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
+            HttpException::RangeNotSatisfiable => "RANGE_NOT_SATISFIABLE",
+            HttpException::TooManyRequests => "TOO_MANY_REQUESTS",
+            HttpException::ServiceUnavailable => "SERVICE_UNAVAILABLE",
+            HttpException::InternalError => "INTERNAL_ERROR",
+            HttpException::InsufficientStorage => "INSUFFICIENT_STORAGE",
+        }
+    }
+}
+
+/// Error body shared by every failed response: `{ "code", "message", "details" }`
+pub fn error_body(
+    code: &str,
+    message: &str,
+    details: Option<serde_json::Value>,
+) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "code": code,
+        "message": message,
+        "details": details,
+    }))
 }
 
 pub struct HttpError {
     pub error: Option<anyhow::Error>,
     pub exception: HttpException,
     pub custom_message: Option<String>,
+    pub code: Option<&'static str>,
+    pub details: Option<serde_json::Value>,
 }
 
 impl HttpError {
-    pub fn get_msg(self) -> String {
-        if let Some(message) = self.custom_message {
-            return message;
+    pub fn get_msg(&self) -> String {
+        if let Some(message) = &self.custom_message {
+            return message.clone();
         }
-        match self.error {
+        match &self.error {
             Some(err) => err.to_string(),
             None => format!("{}", self.exception),
         }
@@ -50,18 +104,23 @@ impl IntoResponse for HttpError {
         if let Some(err) = &self.error {
             tracing::error!("{:?}", err);
         }
-        match self.exception {
-            HttpException::BadRequest => (StatusCode::BAD_REQUEST, self.get_msg()).into_response(),
-            HttpException::Unauthorized => {
-                (StatusCode::UNAUTHORIZED, self.get_msg()).into_response()
-            }
-            HttpException::Forbidden => (StatusCode::FORBIDDEN, self.get_msg()).into_response(),
-            HttpException::NotFound => (StatusCode::NOT_FOUND, self.get_msg()).into_response(),
-            HttpException::RangeNotSatisfiable => {
-                (StatusCode::RANGE_NOT_SATISFIABLE, self.get_msg()).into_response()
-            }
-            _ => (StatusCode::INTERNAL_SERVER_ERROR, self.get_msg()).into_response(),
-        }
+        let status = match self.exception {
+            HttpException::BadRequest => StatusCode::BAD_REQUEST,
+            HttpException::Unauthorized => StatusCode::UNAUTHORIZED,
+            HttpException::Forbidden => StatusCode::FORBIDDEN,
+            HttpException::NotFound => StatusCode::NOT_FOUND,
+            HttpException::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            HttpException::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            HttpException::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            HttpException::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            HttpException::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            HttpException::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            HttpException::InsufficientStorage => StatusCode::INSUFFICIENT_STORAGE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let code = self.code.unwrap_or_else(|| self.exception.code());
+        let body = error_body(code, &self.get_msg(), self.details);
+        (status, body).into_response()
     }
 }
 
@@ -72,6 +131,8 @@ impl From<anyhow::Error> for HttpError {
             error: Some(err),
             exception: HttpException::InternalError,
             custom_message: Some("Something went wrong".to_string()),
+            code: None,
+            details: None,
         }
     }
 }
@@ -83,6 +144,8 @@ impl From<HttpException> for HttpError {
             error: None,
             exception,
             custom_message: None,
+            code: None,
+            details: None,
         }
     }
 }
@@ -94,6 +157,8 @@ impl From<()> for HttpError {
             error: None,
             exception: HttpException::InternalError,
             custom_message: Some("An unexpected error has occurred".to_string()),
+            code: None,
+            details: None,
         }
     }
 }
@@ -104,6 +169,8 @@ impl From<(HttpException, anyhow::Error)> for HttpError {
             error: Some(value.1),
             exception: value.0,
             custom_message: None,
+            code: None,
+            details: None,
         }
     }
 }
@@ -114,6 +181,8 @@ impl From<(HttpException, String)> for HttpError {
             error: None,
             exception: value.0,
             custom_message: Some(value.1),
+            code: None,
+            details: None,
         }
     }
 }
@@ -124,6 +193,8 @@ impl From<(HttpException, &str)> for HttpError {
             error: None,
             exception: value.0,
             custom_message: Some(value.1.to_string()),
+            code: None,
+            details: None,
         }
     }
 }
@@ -134,6 +205,8 @@ impl From<(HttpException, ApiError<'_>)> for HttpError {
             error: None,
             exception: value.0,
             custom_message: Some(value.1.to_string()),
+            code: Some(value.1.code()),
+            details: value.1.details(),
         }
     }
 }
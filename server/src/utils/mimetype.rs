@@ -0,0 +1,43 @@
+/// Guess the mime type from the leading bytes of the content, falling back to the filename
+/// extension and then `application/octet-stream`.
+///
+/// Magic bytes win over the extension, a renamed file is still recognized by what it contains.
+pub fn guess_mimetype(bytes: &[u8], filename: Option<&str>) -> String {
+    if let Some(kind) = infer::get(bytes) {
+        return kind.mime_type().to_string();
+    }
+    filename
+        .and_then(|it| mime_guess::from_path(it).first())
+        .map(|it| it.essence_str().to_string())
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+/// Mime type recognized from the magic bytes at the start of a file, unlike [`guess_mimetype`]
+/// there is no fallback
+pub async fn sniff_mimetype(path: &std::path::Path) -> std::io::Result<Option<String>> {
+    use tokio::io::AsyncReadExt;
+    let mut head = Vec::with_capacity(8192);
+    tokio::fs::File::open(path)
+        .await?
+        .take(8192)
+        .read_to_end(&mut head)
+        .await?;
+    Ok(infer::get(&head).map(|it| it.mime_type().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guess_mimetype() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(guess_mimetype(png, Some("image.txt")), "image/png");
+        let zstd = b"\x28\xb5\x2f\xfd\x04\x58\x00\x00";
+        assert_eq!(guess_mimetype(zstd, None), "application/zstd");
+        let avif = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+        assert_eq!(guess_mimetype(avif, None), "image/avif");
+        assert_eq!(guess_mimetype(b"hello", Some("notes.md")), "text/markdown");
+        assert_eq!(guess_mimetype(b"hello", None), "application/octet-stream");
+    }
+}
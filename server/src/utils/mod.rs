@@ -1,16 +1,168 @@
+use crate::errors::ApiError;
+
+mod access_log;
+mod admin;
+pub mod archive;
+mod content_disposition;
 mod decode_uri;
+mod hash;
 mod http_result;
+mod mimetype;
+mod probe;
+mod shutdown;
+mod throttle;
 mod utc_to_i64;
 
+pub use access_log::*;
+pub use admin::*;
+pub use content_disposition::*;
 pub use decode_uri::*;
+pub use hash::*;
 pub use http_result::*;
+pub use mimetype::*;
+pub use probe::*;
+pub use shutdown::*;
+pub use throttle::*;
 pub use utc_to_i64::*;
 
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
 /// read last_modified from file metadata
 pub fn last_modified(metadata: &std::fs::Metadata) -> Option<String> {
     let modified = metadata.modified().ok()?;
     let utc_date = chrono::DateTime::<chrono::Utc>::from(modified);
-    Some(utc_date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+    Some(utc_date.format(HTTP_DATE_FORMAT).to_string())
+}
+
+/// parse a HTTP-date (e.g. `Sun, 06 Nov 1994 08:49:37 GMT`) into a unix timestamp in seconds
+pub fn parse_http_date(value: &str) -> Option<i64> {
+    chrono::NaiveDateTime::parse_from_str(value.trim(), HTTP_DATE_FORMAT)
+        .ok()
+        .map(|it| it.timestamp())
+}
+
+/// Evaluate the `If-None-Match` and `If-Modified-Since` request headers, returns `true` when
+/// the client's copy is still fresh and a `304` should be sent.
+///
+/// `If-None-Match` takes precedence, `If-Modified-Since` is only checked when it is absent.
+pub fn is_not_modified(
+    headers: &axum::http::HeaderMap,
+    etag: &str,
+    modified: Option<std::time::SystemTime>,
+) -> bool {
+    if let Some(value) = headers.get("if-none-match") {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        return value.split(',').map(str::trim).any(|it| {
+            it == "*" || it.trim_start_matches("W/").trim_matches('"') == etag.trim_matches('"')
+        });
+    }
+    let since = headers
+        .get("if-modified-since")
+        .and_then(|it| it.to_str().ok())
+        .and_then(parse_http_date);
+    match (since, modified) {
+        (Some(since), Some(modified)) => {
+            // HTTP-date has a one second resolution
+            chrono::DateTime::<chrono::Utc>::from(modified).timestamp() <= since
+        }
+        _ => false,
+    }
+}
+
+/// `ETag` value of a stored file, weak when the bytes sent differ from the file, e.g. when it
+/// is compressed on the fly, strong only for the file as is
+pub fn entity_tag(hash: &str, weak: bool) -> String {
+    if weak {
+        format!("W/\"{}\"", hash)
+    } else {
+        format!("\"{}\"", hash)
+    }
+}
+
+/// Whether the `Accept-Encoding` request header accepts gzip, a `q=0` weight refuses it
+pub fn accepts_gzip(headers: &axum::http::HeaderMap) -> bool {
+    let Some(value) = headers.get("accept-encoding") else {
+        return false;
+    };
+    String::from_utf8_lossy(value.as_bytes())
+        .split(',')
+        .any(|it| {
+            let mut params = it.split(';').map(str::trim);
+            let coding = params.next().unwrap_or_default();
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (coding.eq_ignore_ascii_case("gzip") || coding == "*") && !refused
+        })
+}
+
+/// Whether content of the mime type is worth compressing, media and archives are
+/// compressed already
+pub fn is_compressible(mimetype: &str) -> bool {
+    let essence = mimetype
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/toml"
+                | "application/yaml"
+                | "application/x-yaml"
+                | "application/x-sh"
+                | "image/svg+xml"
+        )
+}
+
+/// Whether an `If-Match` header value lists `etag`, the ETag of the current representation or
+/// `None` when there is none. The comparison is strong, weak tags never match.
+pub fn is_match(value: &str, etag: Option<&str>) -> bool {
+    let Some(etag) = etag else {
+        return false;
+    };
+    value.split(',').map(str::trim).any(|it| {
+        it == "*" || (!it.starts_with("W/") && it.trim_matches('"') == etag.trim_matches('"'))
+    })
+}
+
+/// Bytes unprivileged writers can still use on the volume holding `path`, `None` where the
+/// platform or filesystem can't tell
+#[cfg(unix)]
+pub fn available_space(path: &std::path::Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is only read after statvfs filled it
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    // the field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &std::path::Path) -> Option<u64> {
+    None
+}
+
+/// `name` with ` (n)` inserted before its extension, to tell apart files of the same name
+pub fn numbered_name(name: &str, n: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{} ({}).{}", stem, n, ext),
+        _ => format!("{} ({})", name, n),
+    }
 }
 
 pub fn parse_ranges(range_value: &str) -> anyhow::Result<Vec<(Option<u64>, Option<u64>)>> {
@@ -41,25 +193,102 @@ pub fn parse_ranges(range_value: &str) -> anyhow::Result<Vec<(Option<u64>, Optio
     Ok(vec)
 }
 
-pub fn format_ranges(ranges: &[(Option<u64>, Option<u64>)], total: u64) -> String {
+/// Resolve parsed ranges against the total length into inclusive `(start, end)` pairs.
+///
+/// Ranges that cannot be satisfied (start past the end of the content, end before start or a
+/// zero-length suffix such as `bytes=-0`) are dropped, the range set is satisfiable as long as
+/// one of them remains, otherwise the response should be a `416`.
+pub fn resolve_ranges(ranges: &[(Option<u64>, Option<u64>)], total: u64) -> Vec<(u64, u64)> {
     ranges
         .iter()
-        .filter_map(|(start, end)| match (start, end) {
+        .filter_map(|range| match *range {
             // 指定范围的片段
-            (Some(start), Some(end)) => Some(format!("{}-{}/{}", start, end.min(&total), total)),
+            (Some(start), Some(end)) => Some((start, end.min(total.saturating_sub(1)))),
             // 指定起始点
-            (Some(start), None) => Some(format!("{}-{}/{}", start, total - 1, total)),
+            (Some(start), None) => Some((start, total.saturating_sub(1))),
             // 指定末尾的直接数
-            (None, Some(last)) => {
-                let last = last.min(&total);
-                Some(format!("{}-{}/{}", total - last, total, total))
-            }
+            (None, Some(last)) => Some((total - last.min(total), total.saturating_sub(1))),
             _ => None,
         })
+        .filter(|(start, end)| *start < total && start <= end)
+        .collect()
+}
+
+/// Sort the ranges and coalesce the overlapping or adjacent ones
+pub fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Parse a `Range` header value and resolve it against the total length, the result is merged
+/// and never empty. Returns the reason when the header can't be served, which should be
+/// answered with a `416`.
+///
+/// Headers listing more than `max_ranges` ranges are refused before they are merged, so the
+/// parts of a `multipart/byteranges` response and their boundary overhead stay bounded.
+pub fn evaluate_ranges(
+    range_value: &str,
+    total: u64,
+    max_ranges: usize,
+) -> Result<Vec<(u64, u64)>, ApiError<'static>> {
+    let ranges = parse_ranges(range_value).map_err(|_| ApiError::InvalidRange)?;
+    if ranges.len() > max_ranges {
+        return Err(ApiError::RangeTooLarge);
+    }
+    let ranges = merge_ranges(resolve_ranges(&ranges, total));
+    if ranges.is_empty() {
+        return Err(ApiError::RangeNotFound);
+    }
+    Ok(ranges)
+}
+
+/// Parse a `Content-Range: bytes <start>-<end>/<total>` request header
+pub fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end, total) = (
+        start.trim().parse().ok()?,
+        end.trim().parse().ok()?,
+        total.trim().parse().ok()?,
+    );
+    (start <= end && end < total).then_some((start, end, total))
+}
+
+pub fn format_ranges(ranges: &[(u64, u64)], total: u64) -> String {
+    ranges
+        .iter()
+        .map(|(start, end)| format!("{}-{}/{}", start, end, total))
         .collect::<Vec<_>>()
         .join(", ")
 }
 
+/// Delimiter and headers opening a part of a `multipart/byteranges` body, the closing
+/// delimiter is [`byteranges_end`]
+pub fn byteranges_part(
+    boundary: &str,
+    content_type: &str,
+    range: (u64, u64),
+    total: u64,
+) -> String {
+    format!(
+        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}\r\n\r\n",
+        boundary,
+        content_type,
+        format_ranges(&[range], total)
+    )
+}
+
+pub fn byteranges_end(boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,6 +300,118 @@ mod tests {
         assert!(last_modified(&metadata).is_some())
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_available_space() {
+        assert!(available_space(std::path::Path::new(".")).is_some_and(|it| it > 0));
+        assert_eq!(
+            available_space(std::path::Path::new("does-not-exist")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(
+            parse_http_date("Thu, 01 Jun 2023 09:22:40 GMT"),
+            Some(1685611360)
+        );
+        assert_eq!(parse_http_date("2023-06-01 09:22:40"), None);
+    }
+
+    #[test]
+    fn test_entity_tag() {
+        assert_eq!(entity_tag("abc", false), "\"abc\"");
+        assert_eq!(entity_tag("abc", true), "W/\"abc\"");
+        let headers = |value: &str| {
+            let mut headers = axum::http::HeaderMap::new();
+            headers.insert("if-none-match", value.parse().unwrap());
+            headers
+        };
+        // revalidation uses the weak comparison, either form matches
+        assert!(is_not_modified(
+            &headers(&entity_tag("abc", true)),
+            "abc",
+            None
+        ));
+        assert!(is_not_modified(
+            &headers(&entity_tag("abc", false)),
+            "abc",
+            None
+        ));
+        // If-Match uses the strong one, a transformed copy can't be matched
+        assert!(is_match(&entity_tag("abc", false), Some("abc")));
+        assert!(!is_match(&entity_tag("abc", true), Some("abc")));
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        use axum::http::HeaderMap;
+        use std::time::{Duration, UNIX_EPOCH};
+        let modified = Some(UNIX_EPOCH + Duration::from_millis(1685611360500));
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (key, value) in pairs {
+                headers.insert(*key, value.parse().unwrap());
+            }
+            headers
+        };
+        // not modified since
+        assert!(is_not_modified(
+            &headers(&[("if-modified-since", "Thu, 01 Jun 2023 09:22:40 GMT")]),
+            "abc",
+            modified
+        ));
+        // modified after
+        assert!(!is_not_modified(
+            &headers(&[("if-modified-since", "Thu, 01 Jun 2023 09:22:39 GMT")]),
+            "abc",
+            modified
+        ));
+        assert!(!is_not_modified(&headers(&[]), "abc", modified));
+        // etag
+        assert!(is_not_modified(
+            &headers(&[("if-none-match", "\"xyz\", W/\"abc\"")]),
+            "abc",
+            modified
+        ));
+        // a mismatched etag wins over a fresh date
+        assert!(!is_not_modified(
+            &headers(&[
+                ("if-none-match", "xyz"),
+                ("if-modified-since", "Thu, 01 Jun 2023 09:22:40 GMT")
+            ]),
+            "abc",
+            modified
+        ));
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        use axum::http::HeaderMap;
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("accept-encoding", value.parse().unwrap());
+            headers
+        };
+        assert!(accepts_gzip(&headers("gzip, deflate, br")));
+        assert!(accepts_gzip(&headers("br;q=1.0, GZIP;q=0.5")));
+        assert!(accepts_gzip(&headers("*")));
+        assert!(!accepts_gzip(&headers("gzip;q=0")));
+        assert!(!accepts_gzip(&headers("br, identity")));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible("text/plain; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+    }
+
     #[test]
     fn test_parse_ranges() {
         // similar request all bytes of file
@@ -97,25 +438,122 @@ mod tests {
     }
 
     #[test]
-    fn test_format_ranges() {
-        assert_eq!(format_ranges(&[(Some(0), Some(500))], 500), "0-499/500");
-        assert_eq!(format_ranges(&[(Some(0), Some(600))], 500), "0-499/500");
-        assert_eq!(format_ranges(&[(Some(0), None)], 500), "0-500/500");
-        assert_eq!(format_ranges(&[(None, Some(0))], 500), "499-499/500");
-        assert_eq!(format_ranges(&[(None, Some(1))], 500), "499-500/500");
+    fn test_resolve_ranges() {
+        assert_eq!(resolve_ranges(&[(Some(0), Some(499))], 500), vec![(0, 499)]);
+        assert_eq!(resolve_ranges(&[(Some(0), Some(600))], 500), vec![(0, 499)]);
+        assert_eq!(resolve_ranges(&[(Some(0), None)], 500), vec![(0, 499)]);
+        assert_eq!(resolve_ranges(&[(None, Some(1))], 500), vec![(499, 499)]);
+        assert_eq!(resolve_ranges(&[(None, Some(600))], 500), vec![(0, 499)]);
+        // unsatisfiable ranges are dropped
         assert_eq!(
-            format_ranges(&[(Some(0), Some(0)), (None, Some(1))], 500),
-            "0-0/500, 499-500/500"
+            resolve_ranges(&[(Some(0), Some(0)), (Some(500), None)], 500),
+            vec![(0, 0)]
         );
-        assert_eq!(format_ranges(&[], 500), "");
-        assert_eq!(format_ranges(&[(None, None)], 500), "");
+        assert_eq!(resolve_ranges(&[(Some(5), Some(4))], 500), vec![]);
+        // zero-length suffix
+        assert_eq!(resolve_ranges(&[(None, Some(0))], 500), vec![]);
+        // first byte only
+        assert_eq!(resolve_ranges(&[(Some(0), Some(0))], 500), vec![(0, 0)]);
+        // start past the end
+        assert_eq!(resolve_ranges(&[(Some(500), Some(600))], 500), vec![]);
+        assert_eq!(resolve_ranges(&[(Some(0), None)], 0), vec![]);
+        assert_eq!(resolve_ranges(&[(None, None)], 500), vec![]);
+    }
+
+    #[test]
+    fn test_merge_ranges() {
+        // overlap
+        assert_eq!(merge_ranges(vec![(0, 100), (50, 150)]), vec![(0, 150)]);
+        // adjacency
+        assert_eq!(merge_ranges(vec![(0, 9), (10, 19)]), vec![(0, 19)]);
+        // out of order, contained, collapse to a single range
+        assert_eq!(
+            merge_ranges(vec![(200, 300), (0, 99), (100, 199), (220, 230)]),
+            vec![(0, 300)]
+        );
+        // disjoint ranges are kept apart
         assert_eq!(
-            format_ranges(&[(Some(1), None), (None, None)], 500),
-            "1-*/500"
+            merge_ranges(vec![(500, 600), (0, 0)]),
+            vec![(0, 0), (500, 600)]
         );
+        assert_eq!(merge_ranges(vec![]), vec![]);
+    }
+
+    #[test]
+    fn test_evaluate_ranges() {
+        assert_eq!(
+            evaluate_ranges("bytes=0-100,50-150", 500, 8).ok(),
+            Some(vec![(0, 150)])
+        );
+        // out of bounds
+        assert!(matches!(
+            evaluate_ranges("bytes=500-600", 500, 8),
+            Err(ApiError::RangeNotFound)
+        ));
+        // malformed
+        assert!(matches!(
+            evaluate_ranges("bytes=ao-fg", 500, 8),
+            Err(ApiError::InvalidRange)
+        ));
+        assert!(matches!(
+            evaluate_ranges("bytes=0-0,1-1,2-2,3-3,4-4,5-5,6-6,7-7,8-8", 500, 8),
+            Err(ApiError::RangeTooLarge)
+        ));
+        // the limit is counted before merging
+        assert!(matches!(
+            evaluate_ranges("bytes=0-1,0-1,0-1", 500, 2),
+            Err(ApiError::RangeTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_is_match() {
+        assert!(is_match("\"abc\"", Some("abc")));
+        assert!(is_match("abc", Some("abc")));
+        assert!(is_match("\"xyz\", \"abc\"", Some("abc")));
+        assert!(is_match("*", Some("abc")));
+        assert!(!is_match("\"xyz\"", Some("abc")));
+        // strong comparison
+        assert!(!is_match("W/\"abc\"", Some("abc")));
+        // nothing to match once the resource is gone
+        assert!(!is_match("*", None));
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/200"),
+            Some((100, 199, 200))
+        );
+        assert_eq!(parse_content_range("bytes 0-0/1"), Some((0, 0, 1)));
+        // end past the total, reversed, unknown total
+        assert_eq!(parse_content_range("bytes 100-200/200"), None);
+        assert_eq!(parse_content_range("bytes 9-1/200"), None);
+        assert_eq!(parse_content_range("bytes 0-1/*"), None);
+        assert_eq!(parse_content_range("0-1/2"), None);
+        // there is no byte to resume from, so an empty upload never takes the resume branch
+        for value in ["bytes 0-0/0", "bytes 0--1/0", "bytes 0-0/*"] {
+            assert_eq!(parse_content_range(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_format_ranges() {
+        assert_eq!(format_ranges(&[(0, 499)], 500), "0-499/500");
+        assert_eq!(format_ranges(&[(499, 499)], 500), "499-499/500");
+        assert_eq!(
+            format_ranges(&[(0, 0), (499, 499)], 500),
+            "0-0/500, 499-499/500"
+        );
+        assert_eq!(format_ranges(&[], 500), "");
+    }
+
+    #[test]
+    fn test_byteranges_part() {
         assert_eq!(
-            format_ranges(&[(Some(0), Some(0)), (None, None), (None, Some(1))], 500),
-            "0-0/500, 499-500/500"
+            byteranges_part("b0undary", "text/plain", (0, 4), 500),
+            "\r\n--b0undary\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-4/500\r\n\r\n"
         );
+        assert_eq!(byteranges_end("b0undary"), "\r\n--b0undary--\r\n");
     }
 }
@@ -0,0 +1,71 @@
+use std::io::{Read, Seek, SeekFrom};
+
+/// Bytes taken from each end of the file
+pub const PROBE_WINDOW: u64 = 64 * 1024;
+
+/// Quick fingerprint of a file: SHA-256 over its first and last [`PROBE_WINDOW`] bytes
+/// followed by its size as a little-endian u64, hex encoded.
+///
+/// Files up to twice the window are hashed whole, the tail is empty then. Equal probes only
+/// make a duplicate likely, the full hash confirms it.
+pub fn probe_hash(path: &std::path::Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut head = Vec::new();
+    let mut tail = Vec::new();
+    if size <= 2 * PROBE_WINDOW {
+        file.read_to_end(&mut head)?;
+    } else {
+        (&mut file).take(PROBE_WINDOW).read_to_end(&mut head)?;
+        file.seek(SeekFrom::End(-(PROBE_WINDOW as i64)))?;
+        file.read_to_end(&mut tail)?;
+    }
+    Ok(probe_digest(&head, &tail, size))
+}
+
+fn probe_digest(head: &[u8], tail: &[u8], size: u64) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(head);
+    hasher.update(tail);
+    hasher.update(size.to_le_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_hash() {
+        let dir = std::env::temp_dir().join(format!("synclink-probe-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let small = dir.join("small");
+        std::fs::write(&small, b"hello").unwrap();
+        assert_eq!(probe_hash(&small).unwrap(), probe_digest(b"hello", b"", 5));
+        // an empty upload is a valid entry too
+        let empty = dir.join("empty");
+        std::fs::write(&empty, b"").unwrap();
+        assert_eq!(probe_hash(&empty).unwrap(), probe_digest(b"", b"", 0));
+        // only the ends count, the middle can differ
+        let content = |middle: u8| {
+            let mut content = vec![1u8; PROBE_WINDOW as usize];
+            content.extend(vec![middle; 100]);
+            content.extend(vec![2u8; PROBE_WINDOW as usize]);
+            content
+        };
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        std::fs::write(&a, content(0)).unwrap();
+        std::fs::write(&b, content(9)).unwrap();
+        assert_eq!(probe_hash(&a).unwrap(), probe_hash(&b).unwrap());
+        assert_eq!(
+            probe_hash(&a).unwrap(),
+            probe_digest(
+                &vec![1u8; PROBE_WINDOW as usize],
+                &vec![2u8; PROBE_WINDOW as usize],
+                2 * PROBE_WINDOW + 100
+            )
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use axum::body::{BoxBody, Bytes, HttpBody};
+use axum::http::{HeaderMap, Request};
+use axum::{extract::State, middleware::Next, response::Response};
+use http_body::SizeHint;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+/// Coordinates the two phases of a graceful shutdown.
+///
+/// `signal` is cancelled once a shutdown signal arrives: the server stops accepting connections
+/// and long-lived streams (SSE) end. `abort` is cancelled when the grace period runs out:
+/// uploads still in flight stop and remove their partial files.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    pub signal: CancellationToken,
+    pub abort: CancellationToken,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Shutdown {
+    /// Number of requests whose handler has not finished yet
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Response body holding the in-flight guard until the body is fully sent or dropped
+struct TrackedBody {
+    inner: BoxBody,
+    _guard: InFlightGuard,
+}
+
+impl HttpBody for TrackedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Middleware counting in-flight requests, so shutdown can report what it drained.
+///
+/// A request counts until its response body is done, a streamed download included.
+pub async fn track_in_flight<B>(
+    State(shutdown): State<Shutdown>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    shutdown.in_flight.fetch_add(1, Ordering::SeqCst);
+    let guard = InFlightGuard(shutdown.in_flight.clone());
+    next.run(request).await.map(|inner| {
+        axum::body::boxed(TrackedBody {
+            inner,
+            _guard: guard,
+        })
+    })
+}
+
+/// End `stream` early once `token` is cancelled, so a slow download releases its file handle
+/// instead of outliving the shutdown grace period
+pub fn until_cancelled<S>(stream: S, token: CancellationToken) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    async_stream::stream! {
+        tokio::pin!(stream);
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    tracing::warn!("Shutdown: response stream ended before completion");
+                    break;
+                }
+                item = stream.next() => match item {
+                    Some(item) => yield item,
+                    None => break,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_until_cancelled() {
+        let token = CancellationToken::new();
+        let stream = until_cancelled(tokio_stream::iter(0..), token.clone());
+        tokio::pin!(stream);
+        assert_eq!(stream.next().await, Some(0));
+        assert_eq!(stream.next().await, Some(1));
+        token.cancel();
+        assert_eq!(stream.next().await, None);
+    }
+}
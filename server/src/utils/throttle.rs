@@ -0,0 +1,59 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::{Stream, StreamExt};
+
+/// Hold back the chunks of a response body so it is sent at `rate` bytes per second on average,
+/// `None` passes the stream through untouched.
+///
+/// The pause happens between chunks, so the chunk size bounds how bursty the output is.
+pub fn throttle<S, B, E>(stream: S, rate: Option<u64>) -> impl Stream<Item = Result<B, E>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+{
+    async_stream::stream! {
+        tokio::pin!(stream);
+        let start = Instant::now();
+        let mut sent = 0u64;
+        while let Some(item) = stream.next().await {
+            if let (Some(rate), Ok(chunk)) = (rate, &item) {
+                sent += chunk.as_ref().len() as u64;
+                tokio::time::sleep_until(start + Duration::from_secs_f64(sent as f64 / rate as f64))
+                    .await;
+            }
+            yield item;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let chunks = || tokio_stream::iter((0..3).map(|_| Ok::<_, ()>(vec![0u8; 100])));
+        let start = std::time::Instant::now();
+        let stream = throttle(chunks(), Some(1000));
+        tokio::pin!(stream);
+        let mut size = 0;
+        while let Some(Ok(chunk)) = stream.next().await {
+            size += chunk.len();
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(size, 300);
+        // 300 bytes at 1000 bytes per second
+        assert!(elapsed >= Duration::from_millis(290), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+        // unthrottled
+        let start = std::time::Instant::now();
+        let stream = throttle(chunks(), None);
+        tokio::pin!(stream);
+        let mut count = 0;
+        while stream.next().await.is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -27,13 +27,13 @@ export const List: FC = memo(() => {
     page: 1,
     size: 10,
   }));
-  const [total, setTotal] = useState(0);
+  const [hasMore, setHasMore] = useState(false);
   const [list, setList] = useState<IEntity[]>([]);
   const [, { done, error }] = useGet<void>(
     `${__ENDPOINT}?page=${pagination.page}&per_page=${pagination.size}&before=${__TIME}`,
     async (res) => {
       const val = await res.json();
-      setTotal(val.total);
+      setHasMore(val.has_more);
       const ids = new Set(list.map((it) => it.uid));
       setList(
         list.concat((val.data as IEntity[]).filter((it) => !ids.has(it.uid)))
@@ -41,9 +41,8 @@ export const List: FC = memo(() => {
     }
   );
   const previous = useMemo(
-    () =>
-      total > pagination.size * pagination.page ? pagination.page + 1 : void 0,
-    [pagination.page, pagination.size, total]
+    () => (hasMore ? pagination.page + 1 : void 0),
+    [pagination.page, hasMore]
   );
   const loadPrevious = useCallback(() => {
     if (!previous) return void 0;